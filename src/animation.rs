@@ -0,0 +1,250 @@
+use image::{
+    codecs::{
+        gif::{GifDecoder, GifEncoder, Repeat},
+        png::PngDecoder,
+    },
+    imageops, AnimationDecoder, Delay, Frame, RgbaImage,
+};
+use std::{
+    fs::{self, File},
+    io::{BufReader, Write},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    config::{Animation, AnimationFormat, ImageOutput},
+    output,
+};
+
+/// Frame delay of frame folders and of animations without one
+pub const DEFAULT_FRAME_DELAY: u32 = 100;
+
+/// Whether a layer file is animated: an animated GIF or APNG, or a folder of numbered frames
+pub fn is_animated(path: &Path) -> bool {
+    if path.is_dir() {
+        return is_frame_folder(path);
+    }
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    let file = match File::open(path) {
+        Ok(file) => BufReader::new(file),
+        Err(_) => return false,
+    };
+    match extension.as_str() {
+        "gif" => GifDecoder::new(file).is_ok_and(|d| d.into_frames().take(2).count() > 1),
+        "png" | "apng" => PngDecoder::new(file).is_ok_and(|d| d.is_apng().unwrap_or_default()),
+        _ => false,
+    }
+}
+
+/// Whether `path` is a folder of frames named by their position, e.g. `0.png`, `1.png`
+pub fn is_frame_folder(path: &Path) -> bool {
+    frame_files(path).is_some_and(|frames| !frames.is_empty())
+}
+
+/// Frames of a frame folder in order, `None` if anything in it isn't a numbered file
+pub fn frame_files(path: &Path) -> Option<Vec<PathBuf>> {
+    let mut frames = vec![];
+    for entry in path.read_dir().ok()?.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') {
+            continue;
+        }
+        let number = Path::new(&name)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.parse::<u32>().ok());
+        match number {
+            Some(number) if entry.path().is_file() => frames.push((number, entry.path())),
+            _ => return None,
+        }
+    }
+    frames.sort();
+    Some(frames.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Every frame of a layer with how long it is shown in milliseconds, static layers are a single
+/// frame without a delay
+fn read_frames(path: &Path) -> Vec<(RgbaImage, Option<u32>)> {
+    let open = |path: &Path| {
+        image::open(path)
+            .unwrap_or_else(|e| panic!("Could not read layer {}: {}", path.display(), e))
+            .into_rgba8()
+    };
+    if path.is_dir() {
+        let frames = frame_files(path).unwrap_or_else(|| {
+            panic!(
+                "Layer folder {} may only contain numbered frames",
+                path.display()
+            )
+        });
+        return frames.iter().map(|f| (open(f), None)).collect();
+    }
+    if !is_animated(path) {
+        return vec![(open(path), None)];
+    }
+
+    let file = BufReader::new(
+        File::open(path).unwrap_or_else(|_| panic!("Could not read layer {}", path.display())),
+    );
+    let frames = if path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("gif"))
+    {
+        GifDecoder::new(file).and_then(|d| d.into_frames().collect_frames())
+    } else {
+        PngDecoder::new(file)
+            .and_then(|d| d.apng())
+            .and_then(|d| d.into_frames().collect_frames())
+    };
+    frames
+        .unwrap_or_else(|e| panic!("Could not read frames of layer {}: {}", path.display(), e))
+        .into_iter()
+        .map(|frame| {
+            let (numerator, denominator) = frame.delay().numer_denom_ms();
+            (frame.into_buffer(), Some(numerator / denominator.max(1)))
+        })
+        .collect()
+}
+
+/// Composites `layers`, from bottom to top, frame by frame and writes the animation to
+/// `output` with the extension of `settings.format`, plus an MP4 next to it if enabled
+///
+/// The animation is as long as the layer with the most frames, shorter animations loop and
+/// static layers are drawn on every frame. Layers are drawn at their `offsets`. Frames are
+/// timed by `settings.frame_delay`, or else by the longest GIF or APNG layer, as frame folders
+/// have no timing of their own. Frames are resized like still images, variants of `image` are
+/// made from the first frame.
+pub fn create_animation(
+    layers: &[PathBuf],
+    offsets: &[[u32; 2]],
+    settings: &Animation,
+    image: &ImageOutput,
+    output: &Path,
+) {
+    let layers: Vec<_> = layers.iter().map(|l| read_frames(l)).collect();
+    let length = layers
+        .iter()
+        .map(Vec::len)
+        .max()
+        .expect("Token has no layers");
+    let timed = layers
+        .iter()
+        .filter(|frames| frames[0].1.is_some())
+        .max_by_key(|frames| frames.len());
+    let (width, height) = layers[0][0].0.dimensions();
+
+    let frames: Vec<(RgbaImage, u32)> = (0..length)
+        .map(|i| {
+            let mut canvas = RgbaImage::new(width, height);
+            for (frames, [x, y]) in layers.iter().zip(offsets) {
+                let frame = &frames[i % frames.len()].0;
+                imageops::overlay(&mut canvas, frame, *x as i64, *y as i64);
+            }
+            let delay = settings
+                .frame_delay
+                .or_else(|| timed.and_then(|frames| frames[i % frames.len()].1))
+                .unwrap_or(DEFAULT_FRAME_DELAY);
+            (canvas, delay)
+        })
+        .collect();
+    output::save_variants(&frames[0].0, image, output);
+    let frames: Vec<(RgbaImage, u32)> = frames
+        .into_iter()
+        .map(|(frame, delay)| (output::resize(&frame, &image.settings), delay))
+        .collect();
+
+    let path = output.with_extension(settings.format.extension());
+    match settings.format {
+        AnimationFormat::Gif => write_gif(&frames, &path),
+        AnimationFormat::Webp => write_webp(&frames, &path),
+    }
+    if settings.mp4 {
+        write_mp4(&frames, &output.with_extension("mp4"));
+    }
+}
+
+fn write_gif(frames: &[(RgbaImage, u32)], path: &Path) {
+    let file = File::create(path).unwrap_or_else(|_| panic!("Could not create {}", path.display()));
+    let mut encoder = GifEncoder::new_with_speed(file, 10);
+    encoder
+        .set_repeat(Repeat::Infinite)
+        .expect("Could not set GIF to loop");
+    encoder
+        .encode_frames(frames.iter().map(|(image, delay)| {
+            Frame::from_parts(image.clone(), 0, 0, Delay::from_numer_denom_ms(*delay, 1))
+        }))
+        .unwrap_or_else(|e| panic!("Could not write {}: {}", path.display(), e));
+}
+
+fn write_webp(frames: &[(RgbaImage, u32)], path: &Path) {
+    let (width, height) = frames[0].0.dimensions();
+    let config = webp::WebPConfig::new().expect("Could not create WebP config");
+    let mut encoder = webp::AnimEncoder::new(width, height, &config);
+    // Frames only have a start time, the encoder shows the last one as long as the one before
+    let mut timestamp = 0;
+    for (image, delay) in frames {
+        encoder.add_frame(webp::AnimFrame::from_rgba(image, width, height, timestamp));
+        timestamp += *delay as i32;
+    }
+    let data = encoder
+        .try_encode()
+        .unwrap_or_else(|e| panic!("Could not encode {}: {:?}", path.display(), e));
+    fs::write(path, &*data).unwrap_or_else(|_| panic!("Could not write {}", path.display()));
+}
+
+/// Renders the frames to an MP4 with ffmpeg, which must be installed
+fn write_mp4(frames: &[(RgbaImage, u32)], path: &Path) {
+    let frames_directory = path.with_extension("frames");
+    fs::create_dir_all(&frames_directory)
+        .unwrap_or_else(|_| panic!("Could not create {}", frames_directory.display()));
+
+    // Frames are listed for ffmpeg's concat input, which supports a duration per frame
+    let list_path = frames_directory.join("frames.txt");
+    let mut list = File::create(&list_path)
+        .unwrap_or_else(|_| panic!("Could not create {}", list_path.display()));
+    for (i, (image, delay)) in frames.iter().enumerate() {
+        let frame_path = frames_directory.join(format!("{}.png", i));
+        image
+            .save(&frame_path)
+            .unwrap_or_else(|e| panic!("Could not write {}: {}", frame_path.display(), e));
+        writeln!(
+            list,
+            "file '{}.png'\nduration {}",
+            i,
+            *delay as f32 / 1000.0
+        )
+        .expect("Could not write frame list");
+    }
+    // The last frame is repeated, otherwise its duration is ignored
+    writeln!(list, "file '{}.png'", frames.len() - 1).expect("Could not write frame list");
+
+    let status = Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error", "-f", "concat", "-i"])
+        .arg(&list_path)
+        // H.264 needs even dimensions and yuv420p for players to accept it
+        .args([
+            "-vf",
+            "scale=trunc(iw/2)*2:trunc(ih/2)*2",
+            "-pix_fmt",
+            "yuv420p",
+            "-movflags",
+            "+faststart",
+        ])
+        .arg(path)
+        .status()
+        .unwrap_or_else(|_| {
+            panic!(
+                "Could not run ffmpeg to create {}, is it installed?",
+                path.display()
+            )
+        });
+    let _ = fs::remove_dir_all(&frames_directory);
+    if !status.success() {
+        panic!("ffmpeg could not create {}", path.display());
+    }
+}
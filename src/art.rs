@@ -1,22 +1,60 @@
+use image::{ImageDecoder, ImageReader, ImageResult};
 use std::{
-    fs::{read_dir, read_to_string, remove_dir_all},
-    path::Path,
+    collections::{HashMap, HashSet},
+    fs::{create_dir_all, read_dir, read_to_string, remove_dir, remove_dir_all, remove_file},
+    path::{Path, PathBuf},
     process::Command,
     sync::{Arc, Mutex},
     thread,
 };
 
-use crate::metadata::NFTMetadata;
+use crate::{
+    animation,
+    config::{self, Config, Tier},
+    metadata::{NFTMetadata, Trait},
+    output, svg,
+};
 
 const NUM_THREADS: usize = 20;
 
-pub fn generate(_config_location: &String, assets_directory: String, output_directory: String) {
+pub fn generate(
+    config_location: &str,
+    sets: &[String],
+    assets_directory: String,
+    output_directory: String,
+) {
     println!("Generating artwork from metadata...");
 
+    let tiers: &'static [Tier] = Box::leak(
+        config::parse_tiers(config_location, sets)
+            .expect("Error parsing config")
+            .into_boxed_slice(),
+    );
+    let mut layer_files = LayerFiles::new();
+    for tier in tiers {
+        let problems = validate_layers(&tier.config, &assets_directory, &mut layer_files);
+        for warning in problems.warnings {
+            println!("Warning: {}", warning);
+        }
+        if !problems.errors.is_empty() {
+            panic!(
+                "Layers can't be composited, run `resin check` for details:\n{}",
+                problems.errors.join("\n")
+            );
+        }
+    }
+    for (tiers, directory) in config::collection_directories(tiers, &output_directory) {
+        generate_collection(tiers, assets_directory.clone(), directory);
+    }
+}
+
+fn generate_collection(tiers: &'static [Tier], assets_directory: String, output_directory: String) {
     let mut resin_metadata_directory_present = false;
     let metadata_directory = {
         let p = Path::new(&output_directory).join(".resin/");
-        if p.is_dir() {
+        let has_token_metadata = read_dir(&p)
+            .is_ok_and(|mut files| files.any(|f| f.is_ok_and(|f| is_token_metadata(&f.path()))));
+        if has_token_metadata {
             resin_metadata_directory_present = true;
             p.to_string_lossy().to_string()
         } else {
@@ -25,27 +63,47 @@ pub fn generate(_config_location: &String, assets_directory: String, output_dire
     };
 
     read_metadata(
+        tiers,
         Box::leak(assets_directory.into_boxed_str()),
         Box::leak(metadata_directory.clone().into_boxed_str()),
         Box::leak(output_directory.into_boxed_str()),
     );
 
     if resin_metadata_directory_present {
-        let _ = remove_dir_all(metadata_directory);
+        // Other records such as the shuffle permutation are kept
+        for file in read_dir(&metadata_directory)
+            .into_iter()
+            .flatten()
+            .flatten()
+        {
+            if is_token_metadata(&file.path()) {
+                let _ = remove_file(file.path());
+            }
+        }
+        let _ = remove_dir(metadata_directory);
     }
 }
 
+/// Whether `path` is the metadata of a single token, e.g. `5.json`
+fn is_token_metadata(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "json")
+        && path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .is_some_and(|s| s.parse::<u32>().is_ok())
+}
+
 fn read_metadata(
+    tiers: &'static [Tier],
     assets_directory: &'static str,
     metadata_directory: &'static str,
     output_directory: &'static str,
 ) {
-    let files = read_dir(metadata_directory).expect(&format!(
-        "Could not read source directory {}",
-        metadata_directory
-    ));
+    let files = read_dir(metadata_directory)
+        .unwrap_or_else(|_| panic!("Could not read source directory {}", metadata_directory));
     let mut threads: Vec<thread::JoinHandle<()>> = vec![];
-    let num_completed = Arc::new(Mutex::new(0 as u32));
+    let num_completed = Arc::new(Mutex::new(0_u32));
+    let mut failed = 0;
 
     let num_clone = num_completed.clone();
     thread::spawn(move || {
@@ -61,85 +119,419 @@ fn read_metadata(
 
     for file_raw in files {
         let file = file_raw.expect("Could not read file");
-        match file.path().extension() {
-            Some(extension) => {
-                if extension != "json" {
-                    continue;
-                }
-            }
-            None => continue,
+        if !is_token_metadata(&file.path()) {
+            continue;
         }
 
         if threads.len() >= NUM_THREADS {
             let t = threads.remove(0);
-            t.join().expect("Thread failed to finish cleanly");
+            failed += t.join().is_err() as u32;
             *num_completed.lock().unwrap() += 1;
         }
         threads.push(thread::spawn(move || {
-            let contents = read_to_string(file.path()).expect(&format!(
-                "Could not read file contents for file {}",
-                file.path().display()
-            ));
-            let parsed_metadata: NFTMetadata = serde_json::from_str(&contents).expect(&format!(
-                "Could not parse metadata JSON for file {}",
-                file.path().display()
-            ));
+            let contents = read_to_string(file.path()).unwrap_or_else(|_| {
+                panic!(
+                    "Could not read file contents for file {}",
+                    file.path().display()
+                )
+            });
+            let parsed_metadata: NFTMetadata =
+                serde_json::from_str(&contents).unwrap_or_else(|_| {
+                    panic!(
+                        "Could not parse metadata JSON for file {}",
+                        file.path().display()
+                    )
+                });
             let file_name = file.file_name();
             let id = file_name
                 .to_str()
                 .unwrap()
                 .split('.')
                 .next()
-                .expect(&format!(
-                    "Could not get ID from file name for file {}",
-                    file.path().display()
-                ));
+                .unwrap_or_else(|| {
+                    panic!(
+                        "Could not get ID from file name for file {}",
+                        file.path().display()
+                    )
+                });
 
-            create_image(id, &parsed_metadata, &assets_directory, &output_directory);
+            let config = &tiers
+                .iter()
+                .find(|t| t.name == parsed_metadata.tier)
+                .unwrap_or(&tiers[0])
+                .config;
+            create_image(
+                id,
+                &parsed_metadata,
+                config,
+                assets_directory,
+                output_directory,
+            );
         }));
     }
 
     for child in threads {
-        let _ = child.join();
+        failed += child.join().is_err() as u32;
         *num_completed.lock().unwrap() += 1;
     }
+    // Every token is attempted first, the reason each one failed is printed by its thread
+    if failed > 0 {
+        panic!("{} images could not be created", failed);
+    }
 }
 
-fn create_image(id: &str, metadata: &NFTMetadata, assets_directory: &str, output_directory: &str) {
+fn create_image(
+    id: &str,
+    metadata: &NFTMetadata,
+    config: &Config,
+    assets_directory: &str,
+    output_directory: &str,
+) {
     let image_path_buffer = Path::new(output_directory).join(format!("{}.png", id));
-    let image_path = image_path_buffer.to_str().expect(&format!(
-        "Image is not valid path at {}",
-        image_path_buffer.display()
-    ));
+
+    let layers = token_layers(&metadata.attributes, config, assets_directory);
+    let offsets: Vec<[u32; 2]> = layers
+        .iter()
+        .map(|l| l.offset.unwrap_or_default())
+        .collect();
+    let mut layer_paths: Vec<PathBuf> = layers.into_iter().map(|l| l.path).collect();
+    if let Some(missing) = layer_paths.iter().find(|p| !p.exists()) {
+        panic!("Layer does not exist at path {}", missing.display());
+    }
+    if !layer_paths.is_empty() && layer_paths.iter().all(|p| svg::is_svg(p)) {
+        svg::create_image(&layer_paths, &offsets, &config.svg, &image_path_buffer);
+        if config.svg.output.png() {
+            output::finish(&image_path_buffer, &config.image);
+        }
+        return;
+    }
+
+    // SVG layers mixed with other layers are rasterized first
+    let rasterized_directory = Path::new(output_directory).join(format!("{}.layers", id));
+    for (i, layer_path) in layer_paths.iter_mut().enumerate() {
+        if svg::is_svg(layer_path) {
+            create_dir_all(&rasterized_directory)
+                .unwrap_or_else(|_| panic!("Could not create {}", rasterized_directory.display()));
+            let rasterized = rasterized_directory.join(format!("{}.png", i));
+            svg::rasterize(layer_path, &config.svg, &rasterized);
+            *layer_path = rasterized;
+        }
+    }
+
+    // Animated layers can't be composited by vips, they are drawn frame by frame instead
+    if layer_paths.iter().any(|p| animation::is_animated(p)) {
+        animation::create_animation(
+            &layer_paths,
+            &offsets,
+            &config.animation,
+            &config.image,
+            &image_path_buffer,
+        );
+    } else {
+        composite(id, &layer_paths, &offsets, &image_path_buffer);
+        output::finish(&image_path_buffer, &config.image);
+    }
+    let _ = remove_dir_all(rasterized_directory);
+}
+
+/// Composites `layers`, from bottom to top, into the image at `image_path` with vips
+fn composite(id: &str, layer_paths: &[PathBuf], offsets: &[[u32; 2]], image_path_buffer: &Path) {
+    let image_path = image_path_buffer
+        .to_str()
+        .unwrap_or_else(|| panic!("Image is not valid path at {}", image_path_buffer.display()));
 
     let mut composite_command = Command::new("vips");
     composite_command.arg("composite");
 
     let mut layers = vec![];
-    for attribute in &metadata.attributes {
-        if attribute.trait_type.starts_with("_") {
-            continue;
-        }
-        let layer_path_buffer = Path::new(assets_directory)
-            .join(&attribute.trait_type)
-            .join(&attribute.value);
-        let layer_path = layer_path_buffer.to_str().expect(&format!(
-            "Layer is not valid path at {}",
-            layer_path_buffer.display()
-        ));
-        if !layer_path_buffer.exists() {
-            panic!("Layer does not exist at path {}", layer_path);
-        }
-
-        layers.push(layer_path.replace(" ", "\\ ")); // Escape spaces in path
+    for layer_path_buffer in layer_paths {
+        let layer_path = layer_path_buffer.to_str().unwrap_or_else(|| {
+            panic!("Layer is not valid path at {}", layer_path_buffer.display())
+        });
+        layers.push(layer_path.replace(' ', "\\ ")); // Escape spaces in path
     }
 
     composite_command
         .arg(layers.join(" "))
         .arg(image_path)
-        .arg("2") // Use blending mode "source"
+        .arg("2"); // Use blending mode "source"
+
+    // Positions are given for every layer but the bottom one, which is the canvas
+    if offsets.iter().any(|o| *o != [0, 0]) {
+        let position = |axis: usize| {
+            offsets[1..]
+                .iter()
+                .map(|o| o[axis].to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        composite_command
+            .arg("--x")
+            .arg(position(0))
+            .arg("--y")
+            .arg(position(1));
+    }
+
+    composite_command
         .spawn()
-        .expect(&format!("Error creating image {}", id))
+        .unwrap_or_else(|_| panic!("Error creating image {}", id))
         .wait()
-        .expect(&format!("Error creating image {}", id));
+        .unwrap_or_else(|_| panic!("Error creating image {}", id));
+}
+
+/// A file drawn for a token
+pub struct LayerFile {
+    /// Position in the layer stack
+    pub z: f32,
+    pub path: PathBuf,
+    /// `[x, y]` position of a file smaller than the canvas
+    pub offset: Option<[u32; 2]>,
+}
+
+/// Files to composite for a token, from bottom to top
+pub fn layer_paths(attributes: &[Trait], config: &Config, assets_directory: &str) -> Vec<PathBuf> {
+    token_layers(attributes, config, assets_directory)
+        .into_iter()
+        .map(|l| l.path)
+        .collect()
+}
+
+/// Files to composite for a token with their offsets, from bottom to top
+///
+/// Each layer sits at a z-position equal to its index in `attributes`. Traits with `parts`
+/// are drawn as several files, each at its own z-position, instead of the trait file itself.
+pub fn token_layers(
+    attributes: &[Trait],
+    config: &Config,
+    assets_directory: &str,
+) -> Vec<LayerFile> {
+    let mut layers: Vec<LayerFile> = vec![];
+    for attribute in attributes {
+        let z = match config.attributes.get_index_of(&attribute.trait_type) {
+            Some(z) => z as f32,
+            None => continue,
+        };
+        let value = match attribute.value.as_str() {
+            Some(value) if !attribute.trait_type.starts_with('_') => value,
+            _ => continue,
+        };
+        layers.extend(trait_files(
+            &attribute.trait_type,
+            value,
+            z,
+            config,
+            assets_directory,
+        ));
+    }
+
+    // Stable sort keeps config order for parts sharing a z-position
+    layers.sort_by(|a, b| a.z.total_cmp(&b.z));
+    layers
+}
+
+/// Files drawn for the trait `value` of `layer`: its parts, or else the trait file itself
+fn trait_files(
+    layer: &str,
+    value: &str,
+    z: f32,
+    config: &Config,
+    assets_directory: &str,
+) -> Vec<LayerFile> {
+    let layer_directory = Path::new(assets_directory).join(layer);
+    let settings = config.layers.get(layer).and_then(|l| l.traits.get(value));
+    let offset = settings.and_then(|t| t.offset);
+    match settings.map(|t| &t.parts).filter(|p| !p.is_empty()) {
+        Some(parts) => parts
+            .iter()
+            .map(|part| LayerFile {
+                z: part.z.unwrap_or(z),
+                path: layer_directory.join(&part.file),
+                offset: part.offset.or(offset),
+            })
+            .collect(),
+        None => vec![LayerFile {
+            z,
+            path: layer_directory.join(value),
+            offset,
+        }],
+    }
+}
+
+/// Problems with the layer files of a config
+#[derive(Default)]
+pub struct LayerProblems {
+    /// Files that can't be composited
+    pub errors: Vec<String>,
+    /// Files that composite, but probably not as intended
+    pub warnings: Vec<String>,
+}
+
+/// Size and alpha channel of each raster layer file already read, shared between tiers
+pub type LayerFiles = HashMap<PathBuf, Result<((u32, u32), bool), String>>;
+
+/// Problems with the layer files of `config`
+///
+/// Every file a trait can draw must exist and be a readable image. Files without an offset
+/// must be the size most layers share, which is the canvas, and files with one must fit in
+/// it. Files above the bottom layer, which sets the canvas, without an alpha channel hide
+/// everything below them and are only a warning. Raster files are read once into `read`.
+pub fn validate_layers(
+    config: &Config,
+    assets_directory: &str,
+    read: &mut LayerFiles,
+) -> LayerProblems {
+    let mut files: Vec<LayerFile> = vec![];
+    let mut seen: HashSet<(PathBuf, Option<[u32; 2]>)> = HashSet::new();
+    for (z, (layer, tables)) in config.attributes.iter().enumerate() {
+        if layer.starts_with('_') {
+            continue;
+        }
+        for (_, table) in config::weight_tables(tables) {
+            for value in table.keys() {
+                for file in trait_files(layer, value, z as f32, config, assets_directory) {
+                    if seen.insert((file.path.clone(), file.offset)) {
+                        files.push(file);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut problems = LayerProblems::default();
+    let mut layers = vec![];
+    for file in files {
+        if !file.path.exists() {
+            problems
+                .errors
+                .push(format!("{} does not exist", file.path.display()));
+            continue;
+        }
+        // SVG layers are sized by the `svg` settings of each tier
+        let info = match svg::is_svg(&file.path) {
+            true => svg::dimensions(&file.path, &config.svg).map(|size| (size, true)),
+            false => read
+                .entry(file.path.clone())
+                .or_insert_with(|| read_layer(&file.path))
+                .clone(),
+        };
+        match info {
+            Ok(info) => layers.push((file, info)),
+            Err(e) => problems.errors.push(format!(
+                "{} is not a readable image: {}",
+                file.path.display(),
+                e
+            )),
+        }
+    }
+
+    let mut sizes: Vec<((u32, u32), usize)> = vec![];
+    for (_, (size, _)) in layers.iter().filter(|(f, _)| f.offset.is_none()) {
+        match sizes.iter_mut().find(|(s, _)| s == size) {
+            Some((_, count)) => *count += 1,
+            None => sizes.push((*size, 1)),
+        }
+    }
+    // Stable sort keeps the first size seen on a tie
+    sizes.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    let canvas = sizes.first().map(|(size, _)| *size);
+    let bottom = layers.iter().map(|(f, _)| f.z).min_by(f32::total_cmp);
+
+    if let Some((width, height)) = canvas {
+        let image = &config.image;
+        for (name, settings) in std::iter::once(("image", image.settings.clone())).chain(
+            image
+                .variants
+                .iter()
+                .map(|(n, s)| (n.as_str(), s.or(&image.settings))),
+        ) {
+            match settings.scale {
+                Some(scale) if output::scaled_size((width, height), scale).is_none() => {
+                    problems.errors.push(format!(
+                        "The {}x{} canvas is too large to scale by {} for {}",
+                        width, height, scale, name
+                    ))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    for (file, ((width, height), alpha)) in &layers {
+        let path = file.path.display();
+        match (file.offset, canvas) {
+            (None, Some((canvas_width, canvas_height)))
+                if (*width, *height) != (canvas_width, canvas_height) =>
+            {
+                problems.errors.push(format!(
+                    "{} is {}x{} but the other layers are {}x{}, resize it or give it an offset",
+                    path, width, height, canvas_width, canvas_height
+                ))
+            }
+            (Some([x, y]), Some((canvas_width, canvas_height)))
+                if x.saturating_add(*width) > canvas_width
+                    || y.saturating_add(*height) > canvas_height =>
+            {
+                problems.errors.push(format!(
+                    "{} is {}x{} and does not fit in the {}x{} canvas at offset [{}, {}]",
+                    path, width, height, canvas_width, canvas_height, x, y
+                ))
+            }
+            _ => {}
+        }
+        if Some(file.z) == bottom {
+            if file.offset.is_some() {
+                problems.errors.push(format!(
+                    "{} is in the bottom layer, which sets the canvas size and can't have an offset",
+                    path
+                ));
+            }
+        } else if !alpha {
+            problems.warnings.push(format!(
+                "{} has no alpha channel, it hides every layer below it",
+                path
+            ));
+        }
+    }
+    problems
+}
+
+/// Size of a raster layer file and whether it has an alpha channel, checking every frame of
+/// frame folders. Only the headers are read.
+fn read_layer(path: &Path) -> Result<((u32, u32), bool), String> {
+    let frames = match animation::frame_files(path) {
+        Some(frames) if !frames.is_empty() => frames,
+        _ if path.is_dir() => {
+            return Err("folders may only contain frames named by their position".to_string())
+        }
+        _ => vec![path.to_path_buf()],
+    };
+    let mut layer: Option<((u32, u32), bool)> = None;
+    for frame in &frames {
+        let (size, alpha) = read_header(frame).map_err(|e| match path.is_dir() {
+            true => format!("{}: {}", frame.display(), e),
+            false => e.to_string(),
+        })?;
+        match layer {
+            Some((first, _)) if first != size => {
+                return Err(format!(
+                    "{} is {}x{} but the first frame is {}x{}",
+                    frame.display(),
+                    size.0,
+                    size.1,
+                    first.0,
+                    first.1
+                ))
+            }
+            Some((_, ref mut all_alpha)) => *all_alpha &= alpha,
+            None => layer = Some((size, alpha)),
+        }
+    }
+    Ok(layer.expect("Layer has no frames"))
+}
+
+fn read_header(path: &Path) -> ImageResult<((u32, u32), bool)> {
+    let decoder = ImageReader::open(path)?
+        .with_guessed_format()?
+        .into_decoder()?;
+    Ok((decoder.dimensions(), decoder.color_type().has_alpha()))
 }
@@ -0,0 +1,56 @@
+use crate::{art, config, Check};
+
+pub fn handle(options: Check) {
+    let tiers = config::parse_tiers(&options.config, &options.set).expect("Error parsing config");
+    println!("Config is valid");
+
+    let mut layer_files = art::LayerFiles::new();
+    let mut layer_errors = 0;
+    for tier in &tiers {
+        if let Some(name) = &tier.name {
+            println!("\nTier {}", name);
+        }
+        let config = &tier.config;
+        for warning in config::weight_warnings(config) {
+            println!("Warning: {}", warning);
+        }
+        let problems = art::validate_layers(config, &options.assets, &mut layer_files);
+        layer_errors += problems.errors.len();
+        for problem in problems.errors {
+            println!("Layer problem: {}", problem);
+        }
+        for warning in problems.warnings {
+            println!("Warning: {}", warning);
+        }
+
+        println!("Weights of every table, normalized to add up to 100%");
+        for (layer_name, tables) in &config.attributes {
+            println!("\n{}", layer_name);
+            if let Some(p) = config
+                .layers
+                .get(layer_name)
+                .and_then(|l| l.none_probability)
+            {
+                println!("  omitted {:.3}% of the time", p * 100.0);
+            }
+            for (table_name, table) in config::weight_tables(tables) {
+                let total: f32 = table.values().sum();
+                println!(
+                    "  {} (weights add up to {})",
+                    table_name,
+                    config::display_total(total)
+                );
+                for (file, weight) in table {
+                    println!("    {:<32} {:>7.3}%", file, weight / total * 100.0);
+                }
+            }
+        }
+    }
+    // Fails like `generate` would, so check can gate a pipeline
+    if layer_errors > 0 {
+        panic!(
+            "Layers can't be composited, {} problems are listed above",
+            layer_errors
+        );
+    }
+}
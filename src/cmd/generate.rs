@@ -1,23 +1,46 @@
 use crate::Generate;
-use crate::{art, metadata};
+use crate::{art, config, metadata, provenance};
 use std::{fs::remove_dir_all, path::Path};
 
-pub fn handle(options: Generate) {
+pub fn handle(mut options: Generate) {
+    // Image options are shorthands for setting them in the config
+    if let Some(format) = &options.format {
+        options.set.push(format!("image.format={}", format));
+    }
+    if let Some(quality) = options.quality {
+        options.set.push(format!("image.quality={}", quality));
+    }
+
     if !options.skip_metadata {
         println!("Cleaning output directory...");
         let output_directory_path = Path::new(&options.output);
         if output_directory_path.exists() {
-            remove_dir_all(&output_directory_path)
-                .expect("Error occured cleaning output directory");
+            remove_dir_all(output_directory_path).expect("Error occured cleaning output directory");
         }
 
-        metadata::generate(&options.config, &options.assets, &options.output);
+        metadata::generate(
+            &options.config,
+            &options.assets,
+            &options.output,
+            options.seed,
+            &options.set,
+        );
     } else {
         println!("Skipping metadata generation");
     }
 
     if !options.skip_art {
-        art::generate(&options.config, options.assets, options.output);
+        art::generate(
+            &options.config,
+            &options.set,
+            options.assets,
+            options.output.clone(),
+        );
+        let tiers =
+            config::parse_tiers(&options.config, &options.set).expect("Error parsing config");
+        for (tiers, directory) in config::collection_directories(&tiers, &options.output) {
+            provenance::generate(&directory, tiers[0].config.provenance.unwrap_or_default());
+        }
     } else {
         println!("Skipping art generation");
     }
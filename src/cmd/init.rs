@@ -1,17 +1,25 @@
 use indexmap::IndexMap;
 use std::{
-    collections::BTreeMap,
-    fs::{create_dir_all, remove_dir_all, File},
+    collections::{BTreeMap, HashMap},
+    fs::{create_dir_all, read_to_string, remove_dir_all, File},
     io::Write,
     path::Path,
 };
 
+/// Weight of a file without a `#N` suffix or an entry in the folder's weights file
+pub const DEFAULT_WEIGHT: f32 = 0.1;
+const WEIGHT_FILES: [&str; 2] = ["_weights.json", "weights.txt"];
+
 use crate::{
-    config::{Attribute, Config},
+    animation,
+    config::{self, layer_order, Attribute, Config, Layer, TraitSettings},
+    metadata::condition,
     Init,
 };
 
 const EXAMPLE_CONFIG: &str = r#"{
+    "$schema": "./config.schema.json",
+    "version": 1,
     "name": "NFT Title",
     "collectionName":  "NFT Collection",
     "symbol": "SNFT",
@@ -36,10 +44,13 @@ const EXAMPLE_CONFIG: &str = r#"{
         }
     },
     "guaranteedAttributeRolls": [
-        [
-            "FILE_NAME.png",
-            "FILE_NAME_2.png"
-        ]
+        {
+            "traits": {
+                "LAYER_NAME": "FILE_NAME.png",
+                "LAYER_NAME_2": "FILE_NAME_2.png"
+            },
+            "position": "random"
+        }
     ],
     "amount": 10
 }"#;
@@ -57,17 +68,18 @@ fn create_from_scratch(options: Init) {
     let folder_path = Path::new(&options.folder);
     if folder_path.exists() {
         if options.overwrite {
-            remove_dir_all(&folder_path)
+            remove_dir_all(folder_path)
                 .expect("Encountered error removing existing assets directory");
         } else {
             panic!("Folder already exists, pass --overwrite to overwrite");
         }
     }
-    create_dir_all(&folder_path).expect("Encountered error creating new assets directory");
+    create_dir_all(folder_path).expect("Encountered error creating new assets directory");
 
     let mut config_file = File::create(folder_path.join("config.json"))
         .expect("Encountered error creating sample file");
     write!(config_file, "{}", EXAMPLE_CONFIG).expect("Encountered error writing config file");
+    config::write_schema(folder_path);
 
     create_dir_all(folder_path.join("LAYER_NAME"))
         .expect("Encountered error creating assets directory subfolder");
@@ -95,59 +107,268 @@ fn create_from_existing(options: Init) {
     let mut config: Config =
         serde_json::from_str(EXAMPLE_CONFIG).expect("Unable to parse example config");
 
-    config.attributes = IndexMap::new();
+    let (attributes, layers) = scan_assets(assets_path);
+    config.attributes = attributes;
+    config.layers = layers;
+    config.guaranteed_attribute_rolls = vec![];
+
+    let mut value = serde_json::to_value(&config).expect("Could not serialize generated config");
+    let rest = value
+        .as_object_mut()
+        .map(std::mem::take)
+        .unwrap_or_default();
+    let mut with_schema = serde_json::Map::new();
+    with_schema.insert(
+        "$schema".to_string(),
+        format!("./{}", config::SCHEMA_FILE).into(),
+    );
+    with_schema.extend(rest);
+    config::write_schema(assets_path);
+
+    let serialized_config =
+        &serde_json::to_string(&with_schema).expect("Could not serialize generated config JSON");
+    let mut config_file =
+        File::create(config_path).expect("Encountered error creating config file");
+    write!(config_file, "{}", serialized_config).expect("Encountered error writing config file");
+}
+
+/// Layer tables and settings for every folder in `assets_path`, following the folder
+/// conventions in the README
+pub fn scan_assets(
+    assets_path: &Path,
+) -> (
+    IndexMap<String, BTreeMap<String, Attribute>>,
+    IndexMap<String, Layer>,
+) {
+    let mut attributes = IndexMap::new();
+    let mut layers = IndexMap::new();
 
+    let mut layer_folders = vec![];
     for attribute in assets_path
         .read_dir()
         .expect("Encountered error reading assets directory")
     {
         let attribute =
             attribute.expect("Encountered error reading attribute folder in assets directory");
-
-        if !attribute.path().is_dir() {
-            continue;
+        if attribute.path().is_dir() {
+            layer_folders.push(attribute.file_name().to_string_lossy().to_string());
         }
+    }
+    // Numbered folders (01_background) come first in order, the rest alphabetically
+    layer_folders.sort_by_key(|name| {
+        let number = layer_order(name).map(|(n, _)| n);
+        (number.is_none(), number, name.clone())
+    });
 
-        let mut attribute_layers: IndexMap<String, f32> = IndexMap::new();
+    let mut keys = vec![];
+    let mut has_defaults = true;
+    for layer_name in layer_folders {
+        let layer_path = assets_path.join(&layer_name);
+        let mut layer = Layer::default();
+        let mut tables = BTreeMap::new();
 
-        for layer in attribute.path().read_dir().expect(&format!(
-            "Encountered error reading folder in {}",
-            attribute.path().display()
-        )) {
-            let layer = layer.expect(&format!(
-                "Encountered error reading layer in attribute directory {:?}",
-                attribute.file_name()
-            ));
-            let layer_path = layer.path();
+        let defaults = weighted_files(&layer_path, "", &mut layer);
+        if !defaults.is_empty() {
+            tables.insert("_".to_string(), Attribute::Keyed(defaults));
+        }
 
-            if layer_path.is_dir() {
+        // Each sub-folder is a table used when its name matches a key, folders nested inside it
+        // only organise the files
+        for folder in sub_folders(&layer_path) {
+            let key = match table_key(&folder, assets_path) {
+                Some(key) => key,
+                None => {
+                    println!(
+                        "Skipping {}/{}, its conditions can never all match",
+                        layer_name, folder
+                    );
+                    continue;
+                }
+            };
+            let variants = weighted_tree(
+                &layer_path.join(&folder),
+                &format!("{}/", folder),
+                &mut layer,
+            );
+            if variants.is_empty() {
                 continue;
             }
+            for condition in key.split(['&', '|']) {
+                if !condition.contains(':') && !keys.iter().any(|k| k == condition) {
+                    keys.push(condition.to_string());
+                }
+            }
+            tables.insert(key, Attribute::Keyed(variants));
+        }
+        has_defaults &= tables.contains_key("_");
 
-            let layer_name = layer_path.file_name().unwrap().to_str().unwrap();
-            attribute_layers.insert(layer_name.to_string(), 0.1);
+        // Layer names must match the folder, the number is only used for ordering
+        if let Some((_, name)) = layer_order(&layer_name) {
+            layer.display_name = Some(name.to_string());
         }
+        if layer.display_name.is_some() || !layer.traits.is_empty() {
+            layers.insert(layer_name.clone(), layer);
+        }
+        attributes.insert(layer_name, tables);
+    }
 
-        let mut attributes = BTreeMap::new();
-        attributes.insert("_".to_string(), Attribute::Keyed(attribute_layers));
-
-        config.attributes.insert(
-            attribute
-                .path()
-                .file_name()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .to_string(),
-            attributes,
-        );
+    // Plain key names are rolled from a hidden `_key` layer, evaluated before the others
+    if !keys.is_empty() && !attributes.contains_key("_key") {
+        let mut key_layer: BTreeMap<String, Attribute> = keys
+            .into_iter()
+            .map(|k| (k, Attribute::Standard(DEFAULT_WEIGHT)))
+            .collect();
+        // "_" matches no table, so every layer uses its default files
+        if has_defaults {
+            key_layer.insert("_".to_string(), Attribute::Standard(DEFAULT_WEIGHT));
+        }
+        let rest = std::mem::take(&mut attributes);
+        attributes.insert("_key".to_string(), key_layer);
+        attributes.extend(rest);
     }
 
-    config.guaranteed_attribute_rolls = vec![];
+    (attributes, layers)
+}
 
-    let serialized_config =
-        &serde_json::to_string(&config).expect("Could not serialize generated config JSON");
-    let mut config_file =
-        File::create(config_path).expect("Encountered error creating config file");
-    write!(config_file, "{}", serialized_config).expect("Encountered error writing config file");
+/// Table key for a sub-folder, `None` when its conditions can never all match
+///
+/// Trait values are compared without their extension, so `background:blue.png` becomes
+/// `background:blue`. Parts joined with `&` that need two values of the same layer, like
+/// `robot&night` (both rolled from `_key`), are never true at once.
+fn table_key(folder: &str, assets_path: &Path) -> Option<String> {
+    let separator = if folder.contains('&') { '&' } else { '|' };
+    let mut parts: Vec<(&str, &str)> = vec![];
+    for part in folder.split(separator) {
+        let (layer, mut value) = condition(part);
+        if assets_path.join(layer).join(value).is_file() {
+            value = Path::new(value).file_stem()?.to_str()?;
+        }
+        let clashes = parts.iter().any(|(l, v)| *l == layer && *v != value);
+        if separator == '&' && clashes {
+            return None;
+        }
+        parts.push((layer, value));
+    }
+    let parts: Vec<String> = parts
+        .into_iter()
+        .map(|(layer, value)| match layer {
+            "_key" => value.to_string(),
+            _ => format!("{}:{}", layer, value),
+        })
+        .collect();
+    Some(parts.join(&separator.to_string()))
+}
+
+fn sub_folders(folder: &Path) -> Vec<String> {
+    let mut folders: Vec<String> = folder
+        .read_dir()
+        .unwrap_or_else(|_| panic!("Encountered error reading folder {}", folder.display()))
+        .flatten()
+        .filter(|f| f.path().is_dir() && !animation::is_frame_folder(&f.path()))
+        .map(|f| f.file_name().to_string_lossy().to_string())
+        .collect();
+    folders.sort();
+    folders
+}
+
+/// Weights of the files in `folder`, keyed by `prefix` and the file name
+///
+/// Weights come from the folder's `_weights.json` or `weights.txt`, then a `#N` suffix on the
+/// file name (`gold-face#5.png`), then `DEFAULT_WEIGHT`. Suffixed files get a display name
+/// without the suffix in `layer`.
+fn weighted_files(folder: &Path, prefix: &str, layer: &mut Layer) -> IndexMap<String, f32> {
+    let weights = read_weights(folder);
+
+    let mut files: Vec<String> = folder
+        .read_dir()
+        .unwrap_or_else(|_| panic!("Encountered error reading folder {}", folder.display()))
+        .map(|f| {
+            f.unwrap_or_else(|_| panic!("Encountered error reading layer in {}", folder.display()))
+        })
+        // Folders of numbered frames are a single animated file
+        .filter(|f| !f.path().is_dir() || animation::is_frame_folder(&f.path()))
+        .map(|f| f.file_name().to_string_lossy().to_string())
+        .filter(|name| !name.starts_with('.') && !WEIGHT_FILES.contains(&name.as_str()))
+        .collect();
+    files.sort();
+
+    let mut weighted = IndexMap::new();
+    for file_name in files {
+        let stem = Path::new(&file_name)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or(&file_name);
+        let suffix = stem
+            .rsplit_once('#')
+            .and_then(|(name, weight)| Some((name, weight.parse::<f32>().ok()?)));
+
+        let weight = match (weights.get(&file_name), suffix) {
+            (Some(weight), _) => *weight,
+            (None, Some((_, weight))) => weight,
+            (None, None) => DEFAULT_WEIGHT,
+        };
+        let key = format!("{}{}", prefix, file_name);
+        if let Some((name, _)) = suffix {
+            layer.traits.insert(
+                key.clone(),
+                TraitSettings {
+                    display_name: Some(name.to_string()),
+                    ..Default::default()
+                },
+            );
+        }
+        weighted.insert(key, weight);
+    }
+    weighted
+}
+
+/// `weighted_files` of `folder` and every folder inside it
+fn weighted_tree(folder: &Path, prefix: &str, layer: &mut Layer) -> IndexMap<String, f32> {
+    let mut weighted = weighted_files(folder, prefix, layer);
+    for sub_folder in sub_folders(folder) {
+        weighted.extend(weighted_tree(
+            &folder.join(&sub_folder),
+            &format!("{}{}/", prefix, sub_folder),
+            layer,
+        ));
+    }
+    weighted
+}
+
+/// Reads `_weights.json` (`{ "file.png": 5 }`) or `weights.txt` (`file.png 5` per line)
+fn read_weights(folder: &Path) -> HashMap<String, f32> {
+    let json_path = folder.join(WEIGHT_FILES[0]);
+    if json_path.exists() {
+        let contents = read_to_string(&json_path)
+            .unwrap_or_else(|_| panic!("Could not read {}", json_path.display()));
+        return serde_json::from_str(&contents)
+            .unwrap_or_else(|e| panic!("Could not parse {}: {}", json_path.display(), e));
+    }
+
+    let text_path = folder.join(WEIGHT_FILES[1]);
+    if !text_path.exists() {
+        return HashMap::new();
+    }
+    let contents = read_to_string(&text_path)
+        .unwrap_or_else(|_| panic!("Could not read {}", text_path.display()));
+    let mut weights = HashMap::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        // File names may contain spaces, the weight is always last
+        let weight = line
+            .rsplit_once(char::is_whitespace)
+            .and_then(|(file, weight)| Some((file.trim(), weight.parse::<f32>().ok()?)));
+        match weight {
+            Some((file, weight)) => weights.insert(file.to_string(), weight),
+            None => panic!(
+                "Invalid line {} in {}, expected \"FILE_NAME WEIGHT\"",
+                i + 1,
+                text_path.display()
+            ),
+        };
+    }
+    weights
 }
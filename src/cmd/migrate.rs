@@ -0,0 +1,198 @@
+use indexmap::IndexMap;
+use serde_json::{Map, Value};
+use std::{
+    collections::BTreeMap,
+    fs::{read_to_string, File},
+    io::Write,
+    path::Path,
+};
+
+use crate::{
+    config::{self, Attribute, GuaranteedRoll, CONFIG_VERSION, SCHEMA_FILE},
+    format::Format,
+    Migrate,
+};
+
+/// Fields older configs carried that resin never read
+const UNUSED_FIELDS: [&str; 2] = ["creators", "royaltyPercentage"];
+
+pub fn handle(options: Migrate) {
+    println!("Migrating config...");
+
+    let contents = read_to_string(&options.config)
+        .unwrap_or_else(|_| panic!("Could not read config at {}", options.config));
+    let format = Format::from_path(&options.config);
+    let mut config: Value = format
+        .parse(&contents)
+        .unwrap_or_else(|e| panic!("Could not parse config at {}: {}", options.config, e));
+
+    let changes = migrate(&mut config);
+    if changes.is_empty() {
+        println!("Config is up to date");
+        return;
+    }
+    for change in &changes {
+        println!("- {}", change);
+    }
+    if options.dry_run {
+        println!("Dry run, config was not written");
+        return;
+    }
+
+    let updated = format
+        .update(&contents, &config)
+        .unwrap_or_else(|e| panic!("Could not update config at {}: {}", options.config, e));
+    let mut config_file =
+        File::create(&options.config).expect("Encountered error creating config file");
+    write!(config_file, "{}", updated).expect("Encountered error writing config file");
+    let directory = Path::new(&options.config)
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+    config::write_schema(directory);
+    println!("Wrote {}", directory.join(SCHEMA_FILE).display());
+}
+
+/// Upgrades `config` to `CONFIG_VERSION`, returning a description of every change made
+pub fn migrate(config: &mut Value) -> Vec<String> {
+    let mut changes = vec![];
+    let object = config.as_object_mut().expect("Config is not an object");
+
+    let version = object.get("version").and_then(Value::as_u64).unwrap_or(0);
+    if version > CONFIG_VERSION as u64 {
+        panic!(
+            "Config version {} is newer than this version of resin supports ({})",
+            version, CONFIG_VERSION
+        );
+    }
+
+    // `collection: { name, family }` became `collectionName`
+    if let Some(collection) = object.remove("collection") {
+        let name = collection.get("name").and_then(Value::as_str);
+        match name {
+            Some(name) if !object.contains_key("collectionName") => {
+                changes.push(format!(
+                    "Moved collection.name to collectionName \"{}\"",
+                    name
+                ));
+                object.insert("collectionName".to_string(), name.into());
+            }
+            _ => changes.push("Removed collection, collectionName is used instead".to_string()),
+        }
+        if collection.get("family").is_some() {
+            changes.push("Removed collection.family, it is not used".to_string());
+        }
+    }
+    if !object.contains_key("collectionName") {
+        if let Some(name) = object.get("name").cloned() {
+            changes.push("Added collectionName, copied from name".to_string());
+            object.insert("collectionName".to_string(), name);
+        }
+    }
+
+    if let Some(Value::Object(attributes)) = object.get_mut("attributes") {
+        for (layer_name, layer) in attributes.iter_mut() {
+            if layer_name.starts_with('_') {
+                continue;
+            }
+            if let Some(moved) = move_weights_to_default(layer) {
+                changes.push(format!(
+                    "Moved {} weights in attributes.{} into its \"_\" table",
+                    moved, layer_name
+                ));
+            }
+        }
+    }
+
+    migrate_guaranteed_rolls(object, &mut changes);
+
+    for field in UNUSED_FIELDS {
+        if object.contains_key(field) {
+            println!("Warning: {} is not used by resin, it was left as is", field);
+        }
+    }
+
+    if version < CONFIG_VERSION as u64 {
+        changes.push(format!("Set version to {}", CONFIG_VERSION));
+        // Version goes first, after `$schema`
+        let rest = std::mem::take(object);
+        let mut upgraded = Map::new();
+        if let Some(schema) = rest.get("$schema") {
+            upgraded.insert("$schema".to_string(), schema.clone());
+        }
+        upgraded.insert("version".to_string(), CONFIG_VERSION.into());
+        upgraded.extend(
+            rest.into_iter()
+                .filter(|(k, _)| k != "version" && k != "$schema"),
+        );
+        *object = upgraded;
+    }
+    changes
+}
+
+/// Moves plain file weights in a layer into its `"_"` table, returning how many were moved
+fn move_weights_to_default(layer: &mut Value) -> Option<usize> {
+    let layer = layer.as_object_mut()?;
+    let weights: Vec<String> = layer
+        .iter()
+        .filter(|(_, v)| v.is_number())
+        .map(|(k, _)| k.clone())
+        .collect();
+    if weights.is_empty() {
+        return None;
+    }
+
+    let mut default = match layer.get("_") {
+        Some(Value::Object(table)) => table.clone(),
+        _ => Map::new(),
+    };
+    let mut rest = Map::new();
+    for (key, value) in std::mem::take(layer) {
+        if value.is_number() {
+            default.insert(key, value);
+        } else if key != "_" {
+            rest.insert(key, value);
+        }
+    }
+    layer.insert("_".to_string(), Value::Object(default));
+    layer.extend(rest);
+    Some(weights.len())
+}
+
+/// Rewrites guaranteed rolls in the list form, which depends on layer order, as layer maps
+fn migrate_guaranteed_rolls(config: &mut Map<String, Value>, changes: &mut Vec<String>) {
+    let attributes: IndexMap<String, BTreeMap<String, Attribute>> = match config
+        .get("attributes")
+        .and_then(|a| serde_json::from_value(a.clone()).ok())
+    {
+        Some(attributes) => attributes,
+        None => return,
+    };
+    let rolls = match config.get_mut("guaranteedAttributeRolls") {
+        Some(Value::Array(rolls)) => rolls,
+        _ => return,
+    };
+
+    for (i, roll) in rolls.iter_mut().enumerate() {
+        if !roll.is_array() {
+            continue;
+        }
+        let parsed: GuaranteedRoll = match serde_json::from_value(roll.clone()) {
+            Ok(parsed) => parsed,
+            Err(_) => continue,
+        };
+        match parsed.traits(&attributes) {
+            Ok(traits) => {
+                changes.push(format!(
+                    "Rewrote guaranteed roll {} as a map of layers to files",
+                    i
+                ));
+                *roll = serde_json::to_value(GuaranteedRoll::Traits(traits))
+                    .expect("Could not serialize guaranteed roll");
+            }
+            Err(e) => println!(
+                "Warning: guaranteed roll {} was left as is, it could not be matched to layers: {}",
+                i, e
+            ),
+        }
+    }
+}
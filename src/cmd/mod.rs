@@ -1,3 +1,9 @@
+pub mod check;
 pub mod generate;
 pub mod init;
+pub mod migrate;
+pub mod odds;
+pub mod schema;
+pub mod simulate;
+pub mod sync;
 pub mod verify;
@@ -0,0 +1,61 @@
+use crate::{
+    config::{self, Attribute, Config},
+    odds, Odds,
+};
+
+pub fn handle(options: Odds) {
+    let tiers = config::parse_tiers(&options.config, &options.set).expect("Error parsing config");
+
+    for tier in &tiers {
+        if let Some(name) = &tier.name {
+            println!("\nTier {}", name);
+        }
+        let config = &tier.config;
+        let combinations: Vec<odds::Combination> = options
+            .joint
+            .iter()
+            .map(|c| odds::parse_combination(c, config).unwrap_or_else(|e| panic!("{}", e)))
+            .collect();
+        let odds = odds::odds(config, &combinations);
+
+        println!("Chance of each trait in a single roll, before uniqueness re-rolls");
+        for (layer_name, traits) in &odds.layers {
+            println!("\n{}", layer_name);
+            for (value, probability) in traits {
+                println!(
+                    "  {:<32} {:>8.4}%  {}",
+                    value,
+                    probability * 100.0,
+                    configured_weights(config, layer_name, value)
+                );
+            }
+        }
+
+        if !combinations.is_empty() {
+            println!("\nCombinations");
+        }
+        for (source, combination) in options.joint.iter().zip(&combinations) {
+            println!("  {:<48} {:>8.4}%", source, odds.joint(combination) * 100.0);
+        }
+    }
+}
+
+/// Weights of a trait in every table of its layer, e.g. `_: 0.07, joker: 0.11`
+fn configured_weights(config: &Config, layer_name: &str, value: &str) -> String {
+    let mut weights = vec![];
+    for (key, table) in &config.attributes[layer_name] {
+        match table {
+            Attribute::Keyed(files) => {
+                if let Some(weight) = files.get(value) {
+                    weights.push(format!("{}: {}", key, weight));
+                }
+            }
+            Attribute::Standard(weight) if key == value => weights.push(weight.to_string()),
+            Attribute::Standard(_) => {}
+        }
+    }
+    if weights.is_empty() {
+        return String::new();
+    }
+    format!("(weights {})", weights.join(", "))
+}
@@ -0,0 +1,21 @@
+use schemars::schema_for;
+use std::fs;
+
+use crate::{config::Config, metadata::NFTMetadata, Schema, SchemaKind};
+
+pub fn handle(options: Schema) {
+    let schema = match options.kind {
+        SchemaKind::Config => schema_for!(Config),
+        SchemaKind::Metadata => schema_for!(NFTMetadata),
+    };
+    let schema = serde_json::to_string_pretty(&schema).expect("Could not serialize schema");
+
+    match options.output {
+        Some(output) => {
+            fs::write(&output, schema)
+                .unwrap_or_else(|_| panic!("Could not write schema to {}", output));
+            eprintln!("Wrote {}", output);
+        }
+        None => println!("{}", schema),
+    }
+}
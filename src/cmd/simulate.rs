@@ -0,0 +1,40 @@
+use rand::prelude::*;
+
+use crate::{config, simulate, Simulate};
+
+pub fn handle(options: Simulate) {
+    let tiers = config::parse_tiers(&options.config, &options.set).expect("Error parsing config");
+    let seed = options
+        .seed
+        .or(tiers[0].config.seed)
+        .unwrap_or_else(|| thread_rng().gen());
+    println!("Simulating {} runs with seed {}", options.runs, seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    for tier in &tiers {
+        if let Some(name) = &tier.name {
+            println!("\nTier {}", name);
+        }
+        let simulation = simulate::simulate(&tier.config, options.runs, &mut rng);
+        let runs = simulation.runs;
+        println!(
+            "{} rolled tokens per run, trait shares with a 95% confidence interval and the chance of at least one",
+            simulation.rolled / runs.max(1)
+        );
+
+        for (layer_name, traits) in &simulation.layers {
+            println!("\n{}", layer_name);
+            for (value, stats) in traits {
+                let (low, high) = stats.confidence_interval(runs);
+                println!(
+                    "  {:<32} {:>7.3}%  ({:.3}% - {:.3}%)  at least one: {:.1}%",
+                    value,
+                    stats.mean(runs) * 100.0,
+                    low * 100.0,
+                    high * 100.0,
+                    stats.appears(runs) * 100.0
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,358 @@
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::{
+    collections::HashSet,
+    fs::{read_to_string, File},
+    io::Write,
+    path::Path,
+};
+
+use crate::{
+    cmd::init::{scan_assets, DEFAULT_WEIGHT},
+    config::{Attribute, WeightMode},
+    format::Format,
+    Sync,
+};
+
+pub fn handle(options: Sync) {
+    println!("Syncing config with assets directory...");
+
+    let contents = read_to_string(&options.config)
+        .unwrap_or_else(|_| panic!("Could not read config at {}", options.config));
+    let format = Format::from_path(&options.config);
+    let mut config: Value = format
+        .parse(&contents)
+        .unwrap_or_else(|e| panic!("Could not parse config at {}: {}", options.config, e));
+    // Layers from other files can't be written back into them, and copying them here would
+    // duplicate them
+    if let Some(key) = ["extends", "include"]
+        .iter()
+        .find(|k| config.get(**k).is_some())
+    {
+        panic!(
+            "Config at {} uses {}, sync can only update configs that list all of their layers",
+            options.config, key
+        );
+    }
+
+    let changes = sync(&mut config, Path::new(&options.assets), options.remove);
+    if changes.is_empty() {
+        println!("Config is up to date");
+        return;
+    }
+    for change in &changes {
+        println!("{}", change);
+    }
+    if !options.remove && changes.iter().any(|c| c.ends_with(" is missing")) {
+        println!("Pass --remove to remove missing files from the config");
+    }
+    if options.dry_run {
+        println!("Dry run, config was not written");
+        return;
+    }
+
+    let updated = format
+        .update(&contents, &config)
+        .unwrap_or_else(|e| panic!("Could not update config at {}: {}", options.config, e));
+    let mut config_file =
+        File::create(&options.config).expect("Encountered error creating config file");
+    write!(config_file, "{}", updated).expect("Encountered error writing config file");
+}
+
+/// Brings the layers of `config` in line with the files in `assets_directory`
+///
+/// New files are added with the weight `init --from-existing` would give them, existing
+/// weights and ordering are kept. Entries for files that no longer exist are flagged, or
+/// removed when `remove` is set. With a `percent` or `probability` weight mode new files are
+/// only flagged, the user has to rebalance their table. Returns one line per change: `+` added,
+/// `-` removed and `!` missing or left out.
+pub fn sync(config: &mut Value, assets_directory: &Path, remove: bool) -> Vec<String> {
+    let (scanned, mut scanned_layers) = scan_assets(assets_directory);
+    // The one of ones folder sits next to the layers but isn't one
+    let one_of_ones = config
+        .get("oneOfOnes")
+        .and_then(Value::as_str)
+        .and_then(|p| Path::new(p).file_name())
+        .map(|p| p.to_string_lossy().to_string());
+
+    // Added weights would change the total of tables that have to add up to one
+    let total = config
+        .get("weightMode")
+        .and_then(|m| serde_json::from_value::<WeightMode>(m.clone()).ok())
+        .and_then(|m| m.total());
+
+    let mut changes = vec![];
+    let mut new_keys = vec![];
+    let mut trait_settings = vec![];
+    let attributes = config
+        .get_mut("attributes")
+        .and_then(Value::as_object_mut)
+        .expect("Config has no attributes");
+
+    for (layer_name, tables) in scanned {
+        if layer_name == "_key" || one_of_ones.as_ref() == Some(&layer_name) {
+            continue;
+        }
+        let settings = scanned_layers.remove(&layer_name);
+
+        let layer = match attributes.get_mut(&layer_name) {
+            Some(layer) => layer
+                .as_object_mut()
+                .unwrap_or_else(|| panic!("Layer {} is not an object", layer_name)),
+            None => {
+                let files: usize = tables.values().map(table_len).sum();
+                if let Some(total) = total {
+                    changes.push(unbalanced(
+                        &format!("{}/ ({} files)", layer_name, files),
+                        total,
+                    ));
+                    continue;
+                }
+                changes.push(format!("+ {}/ ({} files)", layer_name, files));
+                new_keys.extend(tables.keys().filter(|k| *k != "_").cloned());
+                attributes.insert(layer_name.clone(), to_json(&tables));
+                if let Some(settings) = settings {
+                    trait_settings.push((layer_name, None, settings));
+                }
+                continue;
+            }
+        };
+
+        let known: HashSet<String> = layer
+            .iter()
+            .flat_map(|(key, entry)| match entry.as_object() {
+                Some(table) => table.keys().cloned().collect(),
+                None => vec![key.clone()],
+            })
+            .collect();
+        let mut added = vec![];
+        for (key, table) in tables {
+            let files = match table {
+                Attribute::Keyed(files) => files,
+                Attribute::Standard(_) => continue,
+            };
+            for (file, weight) in files.into_iter().filter(|(f, _)| !known.contains(f)) {
+                if let Some(total) = total {
+                    changes.push(unbalanced(&format!("{}/{}", layer_name, file), total));
+                    continue;
+                }
+                changes.push(format!("+ {}/{} ({})", layer_name, file, weight));
+                // Layers written with plain weights instead of a "_" table stay that way
+                let standard =
+                    key == "_" && !layer.contains_key("_") && layer.values().any(Value::is_number);
+                if standard {
+                    layer.insert(file.clone(), to_json(&weight));
+                } else {
+                    if !layer.contains_key(&key) {
+                        new_keys.push(key.clone());
+                    }
+                    layer
+                        .entry(key.clone())
+                        .or_insert_with(|| Value::Object(Map::new()))
+                        .as_object_mut()
+                        .unwrap_or_else(|| panic!("{} in layer {} is not a table", key, layer_name))
+                        .insert(file.clone(), to_json(&weight));
+                }
+                added.push(file);
+            }
+        }
+        if let Some(settings) = settings {
+            trait_settings.push((layer_name, Some(added), settings));
+        }
+    }
+
+    let mut empty_layers = vec![];
+    for (layer_name, layer) in attributes.iter_mut() {
+        if layer_name.starts_with('_') {
+            continue;
+        }
+        let layer_directory = assets_directory.join(layer_name);
+        let layer = match layer.as_object_mut() {
+            Some(layer) => layer,
+            None => continue,
+        };
+
+        let mut empty_tables = vec![];
+        for (key, entry) in layer.iter_mut() {
+            let table = match entry.as_object_mut() {
+                Some(table) => table,
+                None => continue,
+            };
+            let missing = missing_files(table.iter(), &layer_directory);
+            flag_missing(layer_name, &missing, remove, &mut changes);
+            if remove {
+                remove_keys(table, &missing);
+                if table.is_empty() {
+                    empty_tables.push(key.clone());
+                }
+            }
+        }
+        // Files with plain weights directly in the layer
+        let missing = missing_files(
+            layer.iter().filter(|(_, v)| v.is_number()),
+            &layer_directory,
+        );
+        flag_missing(layer_name, &missing, remove, &mut changes);
+        if remove {
+            remove_keys(layer, &missing);
+            remove_keys(layer, &empty_tables);
+            if layer.is_empty() {
+                changes.push(format!("- {}/", layer_name));
+                empty_layers.push(layer_name.clone());
+            }
+        }
+    }
+    remove_keys(attributes, &empty_layers);
+
+    add_keys(attributes, new_keys, &mut changes);
+    add_trait_settings(config, trait_settings);
+    changes
+}
+
+fn unbalanced(entry: &str, total: f32) -> String {
+    format!(
+        "! {} is new, add it by hand so its table still adds up to {}",
+        entry, total
+    )
+}
+
+fn missing_files<'a>(
+    entries: impl Iterator<Item = (&'a String, &'a Value)>,
+    layer_directory: &Path,
+) -> Vec<String> {
+    entries
+        .map(|(file, _)| file)
+        .filter(|file| !layer_directory.join(file).exists())
+        .cloned()
+        .collect()
+}
+
+fn flag_missing(layer_name: &str, missing: &[String], remove: bool, changes: &mut Vec<String>) {
+    for file in missing {
+        if remove {
+            changes.push(format!("- {}/{}", layer_name, file));
+        } else {
+            changes.push(format!("! {}/{} is missing", layer_name, file));
+        }
+    }
+}
+
+/// Removes `keys` from `map`, keeping the order of the rest (`Map::remove` swaps in the last
+/// entry)
+fn remove_keys(map: &mut Map<String, Value>, keys: &[String]) {
+    if !keys.is_empty() {
+        *map = std::mem::take(map)
+            .into_iter()
+            .filter(|(k, _)| !keys.contains(k))
+            .collect();
+    }
+}
+
+/// Converts through the JSON text so f32 weights keep their short form, `0.1` instead of
+/// `0.10000000149011612`
+fn to_json(value: &impl Serialize) -> Value {
+    serde_json::to_string(value)
+        .and_then(|json| serde_json::from_str(&json))
+        .expect("Could not serialize weights")
+}
+
+fn table_len(table: &Attribute) -> usize {
+    match table {
+        Attribute::Keyed(files) => files.len(),
+        Attribute::Standard(_) => 1,
+    }
+}
+
+/// Adds plain key names of new tables to the `_key` layer, creating it first if needed
+fn add_keys(attributes: &mut Map<String, Value>, tables: Vec<String>, changes: &mut Vec<String>) {
+    let mut keys: Vec<&str> = vec![];
+    for condition in tables
+        .iter()
+        .flat_map(|t| t.split(['&', '|']))
+        .map(str::trim)
+    {
+        if !condition.contains(':') && !keys.contains(&condition) {
+            keys.push(condition);
+        }
+    }
+    if keys.is_empty() {
+        return;
+    }
+
+    if !attributes.contains_key("_key") {
+        let mut key_layer = Map::new();
+        // "_" matches no table, so every layer uses its default files, as `init` does
+        let has_defaults = attributes.values().all(|layer| {
+            layer
+                .as_object()
+                .is_some_and(|l| l.contains_key("_") || l.values().any(Value::is_number))
+        });
+        if has_defaults {
+            changes.push(format!("+ _key/_ ({})", DEFAULT_WEIGHT));
+            key_layer.insert("_".to_string(), to_json(&DEFAULT_WEIGHT));
+        }
+        // Keys must be rolled before the layers that use them
+        let rest = std::mem::take(attributes);
+        attributes.insert("_key".to_string(), Value::Object(key_layer));
+        attributes.extend(rest);
+    }
+    let key_layer = attributes["_key"]
+        .as_object_mut()
+        .expect("_key layer is not an object");
+    for key in keys {
+        if !key_layer.contains_key(key) {
+            changes.push(format!("+ _key/{} ({})", key, DEFAULT_WEIGHT));
+            key_layer.insert(key.to_string(), to_json(&DEFAULT_WEIGHT));
+        }
+    }
+}
+
+/// Copies display names from the folder conventions for added layers and files, settings
+/// already in the config are kept
+fn add_trait_settings(
+    config: &mut Value,
+    settings: Vec<(String, Option<Vec<String>>, crate::config::Layer)>,
+) {
+    for (layer_name, added, scanned) in settings {
+        let display_name = scanned.display_name.filter(|_| added.is_none());
+        let traits: Vec<_> = scanned
+            .traits
+            .into_iter()
+            .filter(|(file, _)| match &added {
+                Some(added) => added.contains(file),
+                None => true,
+            })
+            .collect();
+        if display_name.is_none() && traits.is_empty() {
+            continue;
+        }
+
+        let config = config.as_object_mut().expect("Config is not an object");
+        let layers = config
+            .entry("layers")
+            .or_insert_with(|| Value::Object(Map::new()))
+            .as_object_mut()
+            .expect("layers is not an object");
+        let layer = layers
+            .entry(layer_name)
+            .or_insert_with(|| Value::Object(Map::new()))
+            .as_object_mut()
+            .expect("Layer settings are not an object");
+
+        if let Some(display_name) = display_name {
+            layer
+                .entry("displayName")
+                .or_insert_with(|| display_name.into());
+        }
+        for (file, trait_settings) in traits {
+            let traits = layer
+                .entry("traits")
+                .or_insert_with(|| Value::Object(Map::new()))
+                .as_object_mut()
+                .expect("traits is not an object");
+            traits.entry(file).or_insert_with(|| {
+                serde_json::to_value(trait_settings).expect("Could not serialize trait settings")
+            });
+        }
+    }
+}
@@ -1,5 +1,14 @@
-use crate::Verify;
+use crate::{provenance, Verify};
 
 pub fn handle(options: Verify) {
-    println!("verifying generated assets directory at {}", options.folder)
+    println!("verifying generated assets directory at {}", options.folder);
+
+    let problems = provenance::verify(&options.folder);
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    if !problems.is_empty() {
+        panic!("Verification failed with {} problems", problems.len());
+    }
+    println!("All files match the manifest");
 }
@@ -1,32 +1,1345 @@
 use indexmap::IndexMap;
-use serde::{Deserialize, Serialize};
-use serde_json::Result;
-use std::{collections::BTreeMap, fs};
+use schemars::JsonSchema;
+use serde::{de::Error, Deserialize, Serialize};
+use serde_json::{Map, Number, Result, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
 
+use crate::{format::Format, metadata::Trait, template::Template};
+
+/// Current version of the config format, older configs are upgraded by `resin migrate`
+pub const CONFIG_VERSION: u32 = 1;
+/// JSON Schema of the config, referenced by `$schema` for editor autocompletion
+pub const SCHEMA_FILE: &str = "config.schema.json";
+
+/// A tier of the collection, configs without `tiers` have a single unnamed tier
+pub struct Tier {
+    pub name: Option<String>,
+    pub config: Config,
+}
+
+/// Parses the config at `location`, the first tier of configs with `tiers`
+#[cfg(test)]
 pub fn parse(location: &str) -> Result<Config> {
+    Ok(parse_tiers(location, &[])?.remove(0).config)
+}
+
+/// Parses the config at `location` into its tiers
+///
+/// `sets` are `KEY=VALUE` overrides applied after `extends` and `include` are resolved, where
+/// `KEY` is a dotted path like `templates.name` and `VALUE` is JSON or a plain string. `RESIN_`
+/// environment variables are applied before them, see `env_set`.
+pub fn parse_tiers(location: &str, sets: &[String]) -> Result<Vec<Tier>> {
     let config_file = fs::read_to_string(location).expect("Could not read configuration file");
-    let config: Config = serde_json::from_str(&config_file)?;
+    let format = Format::from_path(location);
+    let mut value: Value = format.parse(&config_file)?;
+
+    let composed = value.get("extends").is_some() || value.get("include").is_some();
+    if composed {
+        value = resolve(value, Path::new(location), &mut vec![])?;
+    }
+    let env_sets: Vec<String> = std::env::vars()
+        .filter_map(|(name, raw)| env_set(&name, &raw))
+        .collect();
+    for set in env_sets.iter().chain(sets) {
+        apply_set(&mut value, set)?;
+    }
+    check_version(&value)?;
+
+    let tiers = match value.as_object_mut().and_then(|c| c.remove("tiers")) {
+        Some(Value::Object(tiers)) if !tiers.is_empty() => tiers,
+        _ => {
+            // Parsed from the file itself where possible so errors keep their line and column
+            let config: Config = if composed || !sets.is_empty() || !env_sets.is_empty() {
+                serde_json::from_value(value)?
+            } else {
+                format.parse(&config_file)?
+            };
+            return Ok(vec![Tier {
+                name: None,
+                config: load(config, location)?,
+            }]);
+        }
+    };
+
+    let mut parsed = vec![];
+    for (name, overlay) in tiers {
+        // Guaranteed rolls and one of ones are made once, in the first tier
+        if let Some(key) = ["guaranteedAttributeRolls", "oneOfOnes"]
+            .into_iter()
+            .find(|key| overlay.get(key).is_some())
+        {
+            return Err(Error::custom(format!(
+                "Tier {} can't set {}, it is made once for the whole collection in the first tier",
+                name, key
+            )));
+        }
+        let mut tier_value = value.clone();
+        if !parsed.is_empty() {
+            if let Some(tier_value) = tier_value.as_object_mut() {
+                tier_value.insert("guaranteedAttributeRolls".to_string(), Value::Array(vec![]));
+                tier_value.remove("oneOfOnes");
+            }
+        }
+        merge(&mut tier_value, overlay);
+        let mut config: Config = serde_json::from_value(tier_value)
+            .map_err(|e| Error::custom(format!("Invalid tier {}: {}", name, e)))?;
+        config.tier = Some(name.clone());
+
+        parsed.push(Tier {
+            name: Some(name),
+            config: load(config, location)?,
+        });
+    }
+    Ok(parsed)
+}
+
+/// Output folder of each collection, tiers in their own folders or all of them in `output_directory`
+pub fn collection_directories<'a>(
+    tiers: &'a [Tier],
+    output_directory: &str,
+) -> Vec<(&'a [Tier], String)> {
+    match tiers[0].config.tier_output {
+        TierOutput::Contiguous => vec![(tiers, output_directory.to_string())],
+        TierOutput::Folders => tiers
+            .iter()
+            .map(|tier| {
+                let directory =
+                    Path::new(output_directory).join(tier.name.as_deref().unwrap_or(""));
+                (
+                    std::slice::from_ref(tier),
+                    directory.to_string_lossy().to_string(),
+                )
+            })
+            .collect(),
+    }
+}
+
+/// Merges `overlay` into `base`, objects are merged key by key except layer tables, which are replaced
+pub fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(Value::Object(layers)) if key == "attributes" => {
+                        if let Value::Object(overlay_layers) = value {
+                            layers.extend(overlay_layers);
+                        }
+                    }
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Refuses configs written for another version of the format
+fn check_version(value: &Value) -> Result<()> {
+    let outdated = match value.get("version").and_then(Value::as_u64) {
+        Some(version) if version > CONFIG_VERSION as u64 => {
+            return Err(Error::custom(format!(
+                "Config version {} is newer than this version of resin supports ({})",
+                version, CONFIG_VERSION
+            )))
+        }
+        Some(version) => version < CONFIG_VERSION as u64,
+        // Unversioned configs are current unless they use the old collection shape
+        None => value.get("collection").is_some() && value.get("collectionName").is_none(),
+    };
+    if outdated {
+        return Err(Error::custom(
+            "Config uses an older format, run `resin migrate` to upgrade it",
+        ));
+    }
+    Ok(())
+}
+
+/// Writes the JSON Schema of the config into `directory`
+pub fn write_schema(directory: &Path) {
+    let schema = serde_json::to_string_pretty(&schemars::schema_for!(Config))
+        .expect("Could not serialize config schema");
+    fs::write(directory.join(SCHEMA_FILE), schema).expect("Could not write config schema");
+}
+
+/// Merges the configs in `extends` and the layer files in `include` under the config read from
+/// `location`, later values override earlier ones
+fn resolve(mut value: Value, location: &Path, seen: &mut Vec<PathBuf>) -> Result<Value> {
+    let canonical = fs::canonicalize(location)
+        .map_err(|e| Error::custom(format!("Could not read {}: {}", location.display(), e)))?;
+    if seen.contains(&canonical) {
+        return Err(Error::custom(format!(
+            "{} extends itself",
+            location.display()
+        )));
+    }
+    let directory = canonical
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .to_owned();
+
+    let config = value
+        .as_object_mut()
+        .ok_or_else(|| Error::custom(format!("{} is not an object", location.display())))?;
+    let extends = path_list(config.remove("extends"), "extends")?;
+    let include = path_list(config.remove("include"), "include")?;
+
+    seen.push(canonical);
+    let mut resolved = Value::Object(Map::new());
+    for base in extends {
+        let path = directory.join(base);
+        let mut base = resolve(read_value(&path)?, &path, seen)?;
+        // Files referenced by a base config are relative to the base config
+        let base_directory = path.parent().unwrap_or_else(|| Path::new(""));
+        for key in ["overrides", "oneOfOnes"] {
+            if let Some(Value::String(file)) = base.get_mut(key) {
+                *file = base_directory.join(&file).to_string_lossy().to_string();
+            }
+        }
+        merge(&mut resolved, base);
+    }
+    seen.pop();
+
+    // Each included file is one layer table, named after the file
+    let mut layers = Map::new();
+    for file in include {
+        let path = directory.join(file);
+        let files = if path.is_dir() {
+            folder_layers(&path)?
+        } else {
+            vec![(file_stem(&path), path)]
+        };
+        for (layer, file) in files {
+            layers.insert(layer, read_value(&file)?);
+        }
+    }
+    if !layers.is_empty() {
+        let mut attributes = Map::new();
+        attributes.insert("attributes".to_string(), Value::Object(layers));
+        merge(&mut resolved, Value::Object(attributes));
+    }
+
+    merge(&mut resolved, value);
+    Ok(resolved)
+}
+
+/// Layer files of an included folder in layer order, with the layer each one is for
+///
+/// Layer order is z-order, so folders of several files number them: `01_background.json` is
+/// the `background` layer, drawn below `02_face.json`.
+fn folder_layers(folder: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let mut files: Vec<PathBuf> = fs::read_dir(folder)
+        .map_err(|e| Error::custom(format!("Could not read {}: {}", folder.display(), e)))?
+        .flatten()
+        .map(|f| f.path())
+        .filter(|f| f.is_file())
+        .collect();
+    files.sort();
+    if let [file] = &files[..] {
+        return Ok(vec![(file_stem(file), file.clone())]);
+    }
+
+    let mut layers = vec![];
+    for file in files {
+        let stem = file_stem(&file);
+        let (number, layer) = layer_order(&stem).ok_or_else(|| {
+            Error::custom(format!(
+                "{} needs a number for its layer order, like 01_background.json, or list the \
+                 files of {} in include in order",
+                file.display(),
+                folder.display()
+            ))
+        })?;
+        layers.push((number, layer.to_string(), file));
+    }
+    layers.sort_by_key(|(number, _, _)| *number);
+    Ok(layers
+        .into_iter()
+        .map(|(_, layer, file)| (layer, file))
+        .collect())
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Splits a numbered name like `01_background` into its position and name
+pub fn layer_order(name: &str) -> Option<(u32, &str)> {
+    let (number, name) = name.split_once('_')?;
+    let number = number.parse().ok()?;
+    (!name.is_empty()).then_some((number, name))
+}
+
+/// A single path or a list of paths
+fn path_list(value: Option<Value>, key: &str) -> Result<Vec<String>> {
+    match value {
+        None => Ok(vec![]),
+        Some(Value::String(path)) => Ok(vec![path]),
+        Some(Value::Array(paths)) => paths
+            .into_iter()
+            .map(|p| match p {
+                Value::String(path) => Ok(path),
+                _ => Err(Error::custom(format!("{} must only contain paths", key))),
+            })
+            .collect(),
+        Some(_) => Err(Error::custom(format!(
+            "{} must be a path or a list of paths",
+            key
+        ))),
+    }
+}
+
+/// Reads a config or layer file in the format given by its extension
+fn read_value(path: &Path) -> Result<Value> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| Error::custom(format!("Could not read {}: {}", path.display(), e)))?;
+    Format::from_path(&path.to_string_lossy())
+        .parse(&contents)
+        .map_err(|e| Error::custom(format!("Invalid {}: {}", path.display(), e)))
+}
+
+/// Turns an environment variable like `RESIN_AMOUNT=500` or `RESIN_TEMPLATES__NAME={{name}}`
+/// into a `--set` override, `__` separates nested keys and `_` words of a camelCase key
+pub fn env_set(name: &str, raw: &str) -> Option<String> {
+    let path = name.strip_prefix("RESIN_")?;
+    let keys: Vec<String> = path
+        .split("__")
+        .map(|key| {
+            let mut words = key.split('_').map(str::to_lowercase);
+            let first = words.next().unwrap_or_default();
+            words.fold(first, |key, word| {
+                let mut chars = word.chars();
+                match chars.next() {
+                    Some(c) => format!("{}{}{}", key, c.to_uppercase(), chars.as_str()),
+                    None => key,
+                }
+            })
+        })
+        .collect();
+    Some(format!("{}={}", keys.join("."), raw))
+}
+
+/// Applies a `KEY=VALUE` override, e.g. `amount=500` or `templates.name={{name}}`
+fn apply_set(value: &mut Value, set: &str) -> Result<()> {
+    let (path, raw) = set
+        .split_once('=')
+        .ok_or_else(|| Error::custom(format!("Invalid --set {}, expected KEY=VALUE", set)))?;
+    let new_value = serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()));
+
+    let mut target = value;
+    for key in path.trim().split('.') {
+        let object = target.as_object_mut().ok_or_else(|| {
+            Error::custom(format!("Invalid --set {}, {} is not an object", set, key))
+        })?;
+        target = object
+            .entry(key)
+            .or_insert_with(|| Value::Object(Map::new()));
+    }
+    *target = new_value;
+    Ok(())
+}
+
+/// Reads the files referenced by a config and validates it
+fn load(mut config: Config, location: &str) -> Result<Config> {
+    let config_directory = Path::new(location)
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+    if let Some(overrides) = &config.overrides {
+        config.token_overrides = read_overrides(&config_directory.join(overrides))?;
+    }
+    if let Some(one_of_ones) = &config.one_of_ones {
+        config.one_of_one_tokens = read_one_of_ones(&config_directory.join(one_of_ones))?;
+    }
+
+    validate(&config)?;
     Ok(config)
 }
 
-#[derive(Serialize, Deserialize)]
+/// Reads every image in `path` along with its optional sidecar JSON, e.g. `founder.png` and `founder.json`
+fn read_one_of_ones(path: &Path) -> Result<Vec<OneOfOne>> {
+    let files = fs::read_dir(path).map_err(|e| {
+        Error::custom(format!(
+            "Could not read one of ones folder {}: {}",
+            path.display(),
+            e
+        ))
+    })?;
+    let mut images: Vec<PathBuf> = files
+        .flatten()
+        .map(|f| f.path())
+        .filter(|p| p.is_file() && p.extension().is_some_and(|e| e != "json"))
+        .collect();
+    images.sort();
+
+    let mut one_of_ones = vec![];
+    for image in images {
+        let sidecar_path = image.with_extension("json");
+        let mut one_of_one: OneOfOne = if sidecar_path.exists() {
+            let sidecar = fs::read_to_string(&sidecar_path).map_err(|e| {
+                Error::custom(format!(
+                    "Could not read one of one {}: {}",
+                    sidecar_path.display(),
+                    e
+                ))
+            })?;
+            serde_json::from_str(&sidecar).map_err(|e| {
+                Error::custom(format!(
+                    "Invalid one of one {}: {}",
+                    sidecar_path.display(),
+                    e
+                ))
+            })?
+        } else {
+            OneOfOne::default()
+        };
+        one_of_one.image = image;
+        one_of_ones.push(one_of_one);
+    }
+    Ok(one_of_ones)
+}
+
+fn read_overrides(path: &Path) -> Result<HashMap<u32, TokenOverride>> {
+    let mut reader = csv::Reader::from_path(path).map_err(|e| {
+        Error::custom(format!(
+            "Could not read overrides file {}: {}",
+            path.display(),
+            e
+        ))
+    })?;
+
+    let mut overrides = HashMap::new();
+    for record in reader.deserialize() {
+        let record: TokenOverride = record.map_err(|e| {
+            Error::custom(format!(
+                "Invalid row in overrides file {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        for template in record.templates() {
+            Template::parse(template).map_err(Error::custom)?;
+        }
+        overrides.insert(record.id, record);
+    }
+    Ok(overrides)
+}
+
+fn validate(config: &Config) -> Result<()> {
+    let image = &config.image;
+    for (name, settings) in std::iter::once(("image", &image.settings))
+        .chain(image.variants.iter().map(|(n, s)| (n.as_str(), s)))
+    {
+        if name.is_empty() || name.contains(['/', '\\']) {
+            return Err(Error::custom(format!(
+                "Invalid image variant name \"{}\"",
+                name
+            )));
+        }
+        if settings.quality.is_some_and(|q| !(1..=100).contains(&q)) {
+            return Err(Error::custom(format!(
+                "quality of {} must be between 1 and 100",
+                name
+            )));
+        }
+        if settings.png_optimization.is_some_and(|o| o > 6) {
+            return Err(Error::custom(format!(
+                "pngOptimization of {} must be between 0 and 6",
+                name
+            )));
+        }
+        if settings.scale == Some(0) || settings.width == Some(0) || settings.height == Some(0) {
+            return Err(Error::custom(format!("Size of {} must not be 0", name)));
+        }
+        // Nearest-neighbour upscaling is for pixel art, whether the scaled canvas fits in
+        // memory is only known once the layers are read by `resin check`
+        if settings.scale.is_some_and(|s| s > 64) {
+            return Err(Error::custom(format!(
+                "scale of {} must be at most 64",
+                name
+            )));
+        }
+    }
+    for (layer_name, tables) in &config.attributes {
+        for (table_name, table) in weight_tables(tables) {
+            if let Some((file, _)) = table.iter().find(|(_, w)| **w < 0.0 || w.is_nan()) {
+                return Err(Error::custom(format!(
+                    "Weight of {} in table {} of layer {} must not be negative",
+                    file, table_name, layer_name
+                )));
+            }
+            let total: f32 = table.values().sum();
+            if total <= 0.0 {
+                return Err(Error::custom(format!(
+                    "Weights of table {} in layer {} add up to 0",
+                    table_name, layer_name
+                )));
+            }
+            if let Some(expected) = config.weight_mode.total() {
+                if !adds_up_to(total, expected) {
+                    return Err(Error::custom(format!(
+                        "Weights of table {} in layer {} add up to {}, but weightMode requires {}",
+                        table_name,
+                        layer_name,
+                        display_total(total),
+                        expected
+                    )));
+                }
+            }
+        }
+    }
+    for (layer_name, layer) in &config.layers {
+        if !config.attributes.contains_key(layer_name) {
+            return Err(Error::custom(format!(
+                "Layer settings given for {}, which is not in attributes",
+                layer_name
+            )));
+        }
+        if let Some(p) = layer.none_probability {
+            if !(0.0..=1.0).contains(&p) {
+                return Err(Error::custom(format!(
+                    "noneProbability for layer {} must be between 0 and 1",
+                    layer_name
+                )));
+            }
+        }
+    }
+    for (trait_type, numeric) in &config.numeric_attributes {
+        let layer_name = config.attributes.keys().find(|layer| {
+            *layer == trait_type
+                || config
+                    .layers
+                    .get(*layer)
+                    .and_then(|l| l.display_name.as_ref())
+                    == Some(trait_type)
+        });
+        if let Some(layer_name) = layer_name {
+            return Err(Error::custom(format!(
+                "Numeric attribute {} has the same trait type as layer {}",
+                trait_type, layer_name
+            )));
+        }
+        if numeric.display_type == Some(DisplayType::Date) {
+            let values = [
+                &numeric.value,
+                &numeric.min,
+                &numeric.max,
+                &numeric.max_value,
+            ];
+            let amounts = numeric.from.values().flat_map(|t| t.values());
+            if values
+                .into_iter()
+                .flatten()
+                .chain(amounts)
+                .any(|n| !n.is_i64() && !n.is_u64())
+            {
+                return Err(Error::custom(format!(
+                    "Numeric attribute {} is a date, its values must be whole seconds",
+                    trait_type
+                )));
+            }
+        }
+        match (&numeric.min, &numeric.max) {
+            (Some(min), Some(max)) => {
+                if min.as_f64() > max.as_f64() {
+                    return Err(Error::custom(format!(
+                        "min for numeric attribute {} is greater than max",
+                        trait_type
+                    )));
+                }
+            }
+            (None, None) => {
+                if numeric.value.is_none() && numeric.from.is_empty() {
+                    return Err(Error::custom(format!(
+                        "Numeric attribute {} needs a value, a min and max, or from",
+                        trait_type
+                    )));
+                }
+            }
+            _ => {
+                return Err(Error::custom(format!(
+                    "Numeric attribute {} needs both min and max",
+                    trait_type
+                )))
+            }
+        }
+        for attribute_name in numeric.from.keys() {
+            if !config.attributes.contains_key(attribute_name) {
+                return Err(Error::custom(format!(
+                    "Numeric attribute {} is derived from {}, which is not in attributes",
+                    trait_type, attribute_name
+                )));
+            }
+        }
+    }
+    for roll in &config.guaranteed_attribute_rolls {
+        let traits = roll.traits(&config.attributes).map_err(Error::custom)?;
+        if let Some(layer) = traits.keys().find(|l| !config.attributes.contains_key(*l)) {
+            return Err(Error::custom(format!(
+                "Guaranteed roll uses layer {}, which is not in attributes",
+                layer
+            )));
+        }
+    }
+    let mut pinned_ids = vec![];
+    for position in config.positions() {
+        if let Some(Position::Id(id)) = position {
+            if id >= config.amount {
+                return Err(Error::custom(format!(
+                    "A token is pinned to ID {}, but amount is {}",
+                    id, config.amount
+                )));
+            }
+            if pinned_ids.contains(&id) {
+                return Err(Error::custom(format!(
+                    "More than one token is pinned to ID {}",
+                    id
+                )));
+            }
+            pinned_ids.push(id);
+        }
+    }
+    if config.positions().count() > config.amount as usize {
+        return Err(Error::custom(format!(
+            "amount is {}, but there are {} guaranteed rolls and one of ones",
+            config.amount,
+            config.positions().count()
+        )));
+    }
+    for one_of_one in &config.one_of_one_tokens {
+        for template in [
+            &one_of_one.name,
+            &one_of_one.description,
+            &one_of_one.external_url,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            Template::parse(template).map_err(Error::custom)?;
+        }
+    }
+    for template in config.templates.all() {
+        let template = Template::parse(template).map_err(Error::custom)?;
+        for variable in template.variables() {
+            let known = match variable.split_once('.') {
+                Some(("trait", layer)) => {
+                    config.attributes.contains_key(layer)
+                        || config.numeric_attributes.contains_key(layer)
+                }
+                Some(("text", layer)) => config.attributes.contains_key(layer),
+                Some(_) => false,
+                None => TEMPLATE_VARIABLES.contains(&variable),
+            };
+            if !known {
+                return Err(Error::custom(format!(
+                    "Unknown template variable {}",
+                    variable
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Variables available in every template, alongside `trait.LAYER` and `text.LAYER`
+const TEMPLATE_VARIABLES: [&str; 9] = [
+    "tier",
+    "name",
+    "collectionName",
+    "symbol",
+    "description",
+    "externalUrl",
+    "id",
+    "edition",
+    "amount",
+];
+
+#[derive(Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Config {
+    /// Version of the config format, see `resin migrate`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
     pub name: String,
     pub collection_name: String,
     pub symbol: String,
     pub description: String,
     pub external_url: String,
     pub attributes: IndexMap<String, BTreeMap<String, Attribute>>,
-    pub guaranteed_attribute_rolls: Vec<Vec<String>>,
+    /// What the weights of a table add up to, see `resin check` for every table normalized
+    #[serde(default, skip_serializing_if = "WeightMode::is_default")]
+    pub weight_mode: WeightMode,
+    /// Per-layer settings, keyed by the same layer names as `attributes`
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub layers: IndexMap<String, Layer>,
+    /// Value written to metadata for omitted layers, the trait is left out if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub none_value: Option<String>,
+    /// How layer and file names are turned into metadata trait types and values
+    #[serde(default, skip_serializing_if = "NameStyle::is_default")]
+    pub name_style: NameStyle,
+    /// Traits with numeric values, keyed by trait type and listed after layer traits
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub numeric_attributes: IndexMap<String, NumericAttribute>,
+    /// Per-token name, description and external URL templates
+    #[serde(default)]
+    pub templates: Templates,
+    /// CSV file, relative to the config, with `id` and any of `name`, `description` and `external_url`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub overrides: Option<String>,
+    /// Rows of the `overrides` file keyed by token ID
+    #[serde(skip)]
+    pub token_overrides: HashMap<u32, TokenOverride>,
+    pub guaranteed_attribute_rolls: Vec<GuaranteedRoll>,
+    /// Folder, relative to the config, of hand-made images added to the collection as they are
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub one_of_ones: Option<String>,
+    /// Images read from the `one_of_ones` folder
+    #[serde(skip)]
+    pub one_of_one_tokens: Vec<OneOfOne>,
     pub amount: u32,
     pub require_unique: Option<bool>,
     pub max_retries: Option<u32>,
+    /// Parts of the collection, each merged over the rest of this config, e.g. its own `amount`
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub tiers: IndexMap<String, Value>,
+    /// Whether tiers share one ID space or are generated into their own folders
+    #[serde(default, skip_serializing_if = "TierOutput::is_default")]
+    pub tier_output: TierOutput,
+    /// Name of the tier this config was merged for
+    #[serde(skip)]
+    pub tier: Option<String>,
+    /// Seed for every random choice, a random seed is used if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// Whether to shuffle token IDs once every roll is generated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shuffle: Option<bool>,
+    /// Whether to also save the provenance hash with every image hash to `.resin/provenance.json`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<bool>,
+    /// How tokens with animated layers are written
+    #[serde(default, skip_serializing_if = "Animation::is_default")]
+    pub animation: Animation,
+    /// How SVG layers are written
+    #[serde(default, skip_serializing_if = "Svg::is_default")]
+    pub svg: Svg,
+    /// Format, size and compression of generated images, and smaller variants of them
+    #[serde(default, skip_serializing_if = "ImageOutput::is_default")]
+    pub image: ImageOutput,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageOutput {
+    #[serde(flatten)]
+    pub settings: ImageSettings,
+    /// Extra sizes written as `{id}-{name}`, e.g. `"thumbnail": { "width": 256 }`, settings not
+    /// given are taken from the full-size image
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub variants: IndexMap<String, ImageSettings>,
+}
+
+impl ImageOutput {
+    pub fn is_default(&self) -> bool {
+        *self == ImageOutput::default()
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageSettings {
+    /// File format, PNG if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<ImageFormat>,
+    /// Quality from 1 to 100 of JPEG, WebP and AVIF images, 90 if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<u8>,
+    /// Whole number from 1 to 64 to upscale the composited image by with nearest-neighbour
+    /// sampling, for pixel art, every layer must then be the same size. Applied before `width`
+    /// and `height`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scale: Option<u32>,
+    /// Width to resize to, the aspect ratio is kept if only one of width and height is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    /// Resampling filter used for resizing, `lanczos3` if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<ResizeFilter>,
+    /// Lossless PNG optimization from 0 (fastest) to 6 (smallest), PNGs aren't optimized if
+    /// not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub png_optimization: Option<u8>,
+}
+
+impl ImageSettings {
+    /// These settings with the ones not set taken from `base`, scale, width and height are
+    /// only taken together so a variant with just a width keeps its aspect ratio
+    pub fn or(&self, base: &ImageSettings) -> ImageSettings {
+        let sized = self.scale.is_some() || self.width.is_some() || self.height.is_some();
+        let size = if sized { self } else { base };
+        ImageSettings {
+            format: self.format.or(base.format),
+            quality: self.quality.or(base.quality),
+            scale: size.scale,
+            width: size.width,
+            height: size.height,
+            filter: self.filter.or(base.filter),
+            png_optimization: self.png_optimization.or(base.png_optimization),
+        }
+    }
+
+    pub fn format(&self) -> ImageFormat {
+        self.format.unwrap_or_default()
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum ImageFormat {
+    #[default]
+    Png,
+    Jpeg,
+    Webp,
+    Avif,
+}
+
+impl ImageFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Webp => "webp",
+            ImageFormat::Avif => "avif",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "image/png",
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Webp => "image/webp",
+            ImageFormat::Avif => "image/avif",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum ResizeFilter {
+    /// Keeps hard pixel edges, for pixel art
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    #[default]
+    Lanczos3,
+}
+
+/// Output of SVG layers, tokens mixing SVG and other layers are always rasterized to PNG
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Svg {
+    /// Files written for tokens whose layers are all SVG
+    #[serde(default)]
+    pub output: SvgOutput,
+    /// Width SVG layers are rasterized at, their own width if neither width nor height is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    /// Height SVG layers are rasterized at, the aspect ratio is kept if only one is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+}
+
+impl Svg {
+    fn is_default(&self) -> bool {
+        *self == Svg::default()
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum SvgOutput {
+    /// A PNG rasterized from the layers
+    Png,
+    /// A single SVG document with every layer nested in it
+    Svg,
+    /// Both, the PNG is used as `image`
+    #[default]
+    Both,
+}
+
+impl SvgOutput {
+    pub fn svg(&self) -> bool {
+        matches!(self, SvgOutput::Svg | SvgOutput::Both)
+    }
+
+    pub fn png(&self) -> bool {
+        matches!(self, SvgOutput::Png | SvgOutput::Both)
+    }
+}
+
+/// Output of tokens with an animated layer, an animated GIF or APNG or a folder of numbered
+/// frames
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Animation {
+    #[serde(default)]
+    pub format: AnimationFormat,
+    /// Milliseconds each frame is shown, taken from the longest animated layer if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frame_delay: Option<u32>,
+    /// Whether to also render an MP4 with ffmpeg, used as `animation_url`
+    #[serde(default)]
+    pub mp4: bool,
+}
+
+impl Animation {
+    fn is_default(&self) -> bool {
+        *self == Animation::default()
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum AnimationFormat {
+    #[default]
+    Gif,
+    Webp,
 }
 
-#[derive(Serialize, Deserialize)]
+impl AnimationFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Webp => "webp",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "image/gif",
+            AnimationFormat::Webp => "image/webp",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, JsonSchema)]
 #[serde(untagged)]
 pub enum Attribute {
     Keyed(IndexMap<String, f32>),
     Standard(f32),
 }
+
+impl Config {
+    /// Requested position of every guaranteed roll followed by every one of one
+    pub fn positions(&self) -> impl Iterator<Item = Option<Position>> + '_ {
+        self.guaranteed_attribute_rolls
+            .iter()
+            .map(|r| r.position())
+            .chain(
+                self.one_of_one_tokens
+                    .iter()
+                    .map(|o| Some(o.position.unwrap_or(Position::Named(NamedPosition::Random)))),
+            )
+    }
+}
+
+/// Weights of a layer as the tables they are rolled from, keyed tables first and the `"_"`
+/// table, together with any plain weights, last
+pub fn weight_tables(tables: &BTreeMap<String, Attribute>) -> Vec<(&str, IndexMap<&str, f32>)> {
+    let mut keyed = vec![];
+    let mut default = IndexMap::new();
+    for (key, attribute) in tables {
+        match attribute {
+            Attribute::Keyed(table) if key == "_" => {
+                default.extend(table.iter().map(|(k, v)| (k.as_str(), *v)))
+            }
+            Attribute::Keyed(table) => keyed.push((
+                key.as_str(),
+                table.iter().map(|(k, v)| (k.as_str(), *v)).collect(),
+            )),
+            Attribute::Standard(weight) => {
+                default.insert(key.as_str(), *weight);
+            }
+        }
+    }
+    if !default.is_empty() {
+        keyed.push(("_", default));
+    }
+    keyed
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum WeightMode {
+    /// Weights only count relative to the rest of their table, `1` and `3` is a 25% and 75% split
+    #[default]
+    Relative,
+    /// Every table adds up to 100
+    Percent,
+    /// Every table adds up to 1
+    Probability,
+}
+
+impl WeightMode {
+    fn is_default(&self) -> bool {
+        *self == WeightMode::Relative
+    }
+
+    /// What every table has to add up to, if anything
+    pub fn total(&self) -> Option<f32> {
+        match self {
+            WeightMode::Relative => None,
+            WeightMode::Percent => Some(100.0),
+            WeightMode::Probability => Some(1.0),
+        }
+    }
+}
+
+/// Whether `total` is `expected`, give or take float rounding of the weights
+fn adds_up_to(total: f32, expected: f32) -> bool {
+    (total - expected).abs() <= expected * 1e-4
+}
+
+/// `total` without the float noise of adding up weights, for messages
+pub fn display_total(total: f32) -> f32 {
+    (total * 1e4).round() / 1e4
+}
+
+/// Tables that are likely mistakes but still roll, like tables with plain weights next to a
+/// `"_"` table, or tables in `relative` mode that don't add up to 1 or are on different scales
+pub fn weight_warnings(config: &Config) -> Vec<String> {
+    let mut warnings = vec![];
+    let mut scales = (None, None);
+    for (layer_name, tables) in &config.attributes {
+        let plain = tables.values().any(|a| matches!(a, Attribute::Standard(_)));
+        // A plain "_" weight, as in `_key`, is an entry of that table rather than a table
+        if plain && matches!(tables.get("_"), Some(Attribute::Keyed(_))) {
+            warnings.push(format!(
+                "Layer {} has plain weights next to a \"_\" table, they are rolled as one table",
+                layer_name
+            ));
+        }
+        if config.weight_mode != WeightMode::Relative {
+            continue;
+        }
+        // Hidden layers are left without a match by rolling "_", visible ones by leaving them out
+        let rest = match layer_name.starts_with('_') {
+            true => "Add a \"_\" entry for the rest",
+            false => "Set noneProbability to leave the layer out",
+        };
+        for (table_name, table) in weight_tables(tables) {
+            let total: f32 = table.values().sum();
+            let table_label = format!("{} in layer {}", table_name, layer_name);
+            if adds_up_to(total, 1.0) {
+                scales.0.get_or_insert(table_label);
+                continue;
+            }
+            if adds_up_to(total, 100.0) {
+                scales.1.get_or_insert(table_label.clone());
+            }
+            // Weights out of 1 that fall short still roll one of the entries every time
+            let rolled = match table.len() {
+                1 => "its only entry is rolled every time",
+                _ => "they are only relative to each other",
+            };
+            warnings.push(format!(
+                "Weights of table {} add up to {}, not 1, {}. {} or set weightMode",
+                table_label,
+                display_total(total),
+                rolled,
+                rest
+            ));
+        }
+    }
+    if let (Some(probability), Some(percent)) = scales {
+        warnings.push(format!(
+            "Table {} adds up to 1 but table {} adds up to 100, set weightMode if they are meant as probabilities or percents",
+            probability, percent
+        ));
+    }
+    warnings
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum TierOutput {
+    /// Tiers follow each other in one collection, in the order they are defined
+    #[default]
+    Contiguous,
+    /// Each tier is its own collection in a subfolder named after it
+    Folders,
+}
+
+impl TierOutput {
+    fn is_default(&self) -> bool {
+        *self == TierOutput::Contiguous
+    }
+}
+
+/// A roll that is always part of the collection
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(untagged)]
+pub enum GuaranteedRoll {
+    /// Traits with an optional position, `{"traits": {"face": "gold-face.png"}, "position": 5}`
+    #[serde(rename_all = "camelCase")]
+    Detailed {
+        traits: IndexMap<String, String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        position: Option<Position>,
+    },
+    /// Traits keyed by layer, `{"face": "gold-face.png"}`
+    Traits(IndexMap<String, String>),
+    /// File names in the same order as the visible (or all) layers in `attributes`
+    Positional(Vec<String>),
+}
+
+impl GuaranteedRoll {
+    /// Traits keyed by layer, layers that are left out are rolled as usual
+    pub fn traits(
+        &self,
+        attributes: &IndexMap<String, BTreeMap<String, Attribute>>,
+    ) -> std::result::Result<IndexMap<String, String>, String> {
+        match self {
+            GuaranteedRoll::Detailed { traits, .. } | GuaranteedRoll::Traits(traits) => {
+                Ok(traits.clone())
+            }
+            GuaranteedRoll::Positional(files) => {
+                let visible: Vec<&String> =
+                    attributes.keys().filter(|k| !k.starts_with('_')).collect();
+                let layers = if files.len() == visible.len() {
+                    visible
+                } else if files.len() == attributes.len() {
+                    attributes.keys().collect()
+                } else {
+                    return Err(format!(
+                        "Guaranteed roll {:?} has {} files, but there are {} layers",
+                        files,
+                        files.len(),
+                        visible.len()
+                    ));
+                };
+                Ok(layers
+                    .into_iter()
+                    .cloned()
+                    .zip(files.iter().cloned())
+                    .collect())
+            }
+        }
+    }
+
+    pub fn position(&self) -> Option<Position> {
+        match self {
+            GuaranteedRoll::Detailed { position, .. } => *position,
+            _ => None,
+        }
+    }
+}
+
+/// Where a guaranteed roll is placed, rolls without one are spread evenly through the collection
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(untagged)]
+pub enum Position {
+    Id(u32),
+    Named(NamedPosition),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum NamedPosition {
+    Spread,
+    Random,
+}
+
+/// A hand-made token, read from an image and its sidecar JSON
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct OneOfOne {
+    #[serde(skip)]
+    pub image: PathBuf,
+    /// Templates in the same format as `templates`, which are used if not set
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub external_url: Option<String>,
+    /// Attributes written to metadata as they are
+    #[serde(default)]
+    pub attributes: Vec<Trait>,
+    /// Placed at a random ID if not set
+    pub position: Option<Position>,
+}
+
+#[derive(Serialize, Deserialize, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Layer {
+    /// Trait type written to metadata instead of the layer name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    /// Probability (0 to 1) that the layer is left out of a roll entirely
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub none_probability: Option<f32>,
+    /// Per-trait settings, keyed by file name
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub traits: IndexMap<String, TraitSettings>,
+}
+
+#[derive(Serialize, Deserialize, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TraitSettings {
+    /// Value written to metadata instead of the file name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    /// Text available to templates as `text.LAYER` when this trait is rolled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// Files drawn in place of the trait file, each at its own z-position
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parts: Vec<Part>,
+    /// `[x, y]` position of a file smaller than the other layers, from the top left
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<[u32; 2]>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Part {
+    /// File name inside the layer folder
+    pub file: String,
+    /// Position in the layer stack, layers sit at their index in `attributes`
+    pub z: Option<f32>,
+    /// `[x, y]` position of the file, the trait's offset if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<[u32; 2]>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Templates {
+    #[serde(default = "default_name_template")]
+    pub name: String,
+    #[serde(default = "default_description_template")]
+    pub description: String,
+    #[serde(default = "default_external_url_template")]
+    pub external_url: String,
+}
+
+impl Templates {
+    fn all(&self) -> [&String; 3] {
+        [&self.name, &self.description, &self.external_url]
+    }
+}
+
+impl Default for Templates {
+    fn default() -> Self {
+        Templates {
+            name: default_name_template(),
+            description: default_description_template(),
+            external_url: default_external_url_template(),
+        }
+    }
+}
+
+fn default_name_template() -> String {
+    "{{name}} #{{id}}".to_string()
+}
+
+fn default_description_template() -> String {
+    "{{description}}".to_string()
+}
+
+fn default_external_url_template() -> String {
+    "{{externalUrl}}".to_string()
+}
+
+#[derive(Deserialize)]
+pub struct TokenOverride {
+    pub id: u32,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub external_url: Option<String>,
+}
+
+impl TokenOverride {
+    fn templates(&self) -> impl Iterator<Item = &String> {
+        [&self.name, &self.description, &self.external_url]
+            .into_iter()
+            .flatten()
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NumericAttribute {
+    /// How marketplaces render the value, plain numbers are shown as rankings
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_type: Option<DisplayType>,
+    /// Fixed value, used as the base for `from` when no range is given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<Number>,
+    /// Inclusive random range, integers if both ends are integers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<Number>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<Number>,
+    /// Amounts added per rolled trait, keyed by layer then file name, `_` matches anything else
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub from: IndexMap<String, IndexMap<String, Number>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_value: Option<Number>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DisplayType {
+    Number,
+    BoostNumber,
+    BoostPercentage,
+    /// Unix timestamp in seconds
+    Date,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum NameStyle {
+    /// Names are used as they are, `heart-eyes`
+    #[default]
+    Raw,
+    /// Dashes and underscores become spaces, `heart eyes`
+    Spaced,
+    /// Spaced with every word capitalized, `Heart Eyes`
+    TitleCase,
+}
+
+impl NameStyle {
+    fn is_default(&self) -> bool {
+        *self == NameStyle::Raw
+    }
+
+    pub fn apply(&self, name: &str) -> String {
+        match self {
+            NameStyle::Raw => name.to_string(),
+            NameStyle::Spaced => name.replace(['-', '_'], " "),
+            NameStyle::TitleCase => NameStyle::Spaced
+                .apply(name)
+                .split(' ')
+                .map(|word| {
+                    let mut chars = word.chars();
+                    match chars.next() {
+                        Some(first) => first.to_uppercase().chain(chars).collect(),
+                        None => String::new(),
+                    }
+                })
+                .collect::<Vec<String>>()
+                .join(" "),
+        }
+    }
+}
@@ -0,0 +1,481 @@
+use serde::{
+    de::{DeserializeOwned, Error as _},
+    Serialize,
+};
+use serde_json::{Error, Map, Result, Value};
+use std::path::Path;
+
+/// File formats a config can be written in, picked by extension
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Format {
+    /// JSON with `//` and `/* */` comments and trailing commas, used for `.json` and `.jsonc`
+    Json,
+    Json5,
+    Yaml,
+    Toml,
+}
+
+impl Format {
+    pub fn from_path(location: &str) -> Format {
+        let extension = Path::new(location)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+        match extension.as_str() {
+            "json5" => Format::Json5,
+            "yaml" | "yml" => Format::Yaml,
+            "toml" => Format::Toml,
+            _ => Format::Json,
+        }
+    }
+
+    /// Deserializes `text`, errors say where in the file the problem is
+    pub fn parse<T: DeserializeOwned>(self, text: &str) -> Result<T> {
+        match self {
+            Format::Json => serde_json::from_str(&strip_comments(text)),
+            Format::Json5 => json5::from_str(text).map_err(|e| {
+                let json5::Error::Message { msg, location } = e;
+                // Syntax errors come with a drawing of the line, the last line says what's wrong
+                let msg = msg.lines().last().unwrap_or_default();
+                let msg = msg.trim().trim_start_matches("= ");
+                match location {
+                    Some(l) => {
+                        Error::custom(format!("{} at line {} column {}", msg, l.line, l.column))
+                    }
+                    None => Error::custom(msg),
+                }
+            }),
+            Format::Yaml => serde_yaml::from_str(text).map_err(|e| match e.location() {
+                Some(l) => Error::custom(format!(
+                    "{} at line {} column {}",
+                    without_location(&e.to_string()),
+                    l.line(),
+                    l.column()
+                )),
+                None => Error::custom(e),
+            }),
+            Format::Toml => toml::from_str(text).map_err(|e| match e.line_col() {
+                Some((line, column)) => Error::custom(format!(
+                    "{} at line {} column {}",
+                    without_location(&e.to_string()),
+                    line + 1,
+                    column + 1
+                )),
+                None => Error::custom(e),
+            }),
+        }
+    }
+
+    /// Serializes `value` over `text`, the config it was read from, keeping the comments and
+    /// layout of everything that didn't change
+    pub fn update(self, text: &str, value: &Value) -> Result<String> {
+        match self {
+            Format::Json | Format::Json5 => {
+                let original: Value = self.parse(text)?;
+                let mut scanner = Scanner::new(text);
+                scanner.skip_trivia();
+                let start = scanner.pos;
+                let (updated, end) = update_value(text, start, &original, value)?;
+                Ok(format!("{}{}{}", &text[..start], updated, &text[end..]))
+            }
+            Format::Yaml | Format::Toml if has_hash_comments(text) => {
+                let name = if self == Format::Yaml { "YAML" } else { "TOML" };
+                Err(Error::custom(format!(
+                    "comments can't be kept when rewriting {} configs, remove them or make the \
+                     changes by hand",
+                    name
+                )))
+            }
+            Format::Yaml | Format::Toml => Ok(self.to_string(value)),
+        }
+    }
+
+    pub fn to_string<T: Serialize>(self, value: &T) -> String {
+        match self {
+            Format::Json | Format::Json5 => {
+                let mut serialized = vec![];
+                let formatter = serde_json::ser::PrettyFormatter::with_indent(b"    ");
+                let mut serializer =
+                    serde_json::Serializer::with_formatter(&mut serialized, formatter);
+                value
+                    .serialize(&mut serializer)
+                    .expect("Could not serialize config");
+                String::from_utf8(serialized).expect("Serialized config is not UTF-8")
+            }
+            Format::Yaml => serde_yaml::to_string(value).expect("Could not serialize config"),
+            Format::Toml => toml::to_string_pretty(value).expect("Could not serialize config"),
+        }
+    }
+}
+
+/// Strips the location other parsers append, so every format reports it the same way
+fn without_location(message: &str) -> &str {
+    message.find(" at line ").map_or(message, |i| &message[..i])
+}
+
+/// Blanks out comments and trailing commas, keeping line and column numbers of the rest intact
+fn strip_comments(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut stripped = String::with_capacity(text.len());
+    let mut in_string = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if in_string {
+            stripped.push(c);
+            if c == '\\' && i + 1 < chars.len() {
+                stripped.push(chars[i + 1]);
+                i += 1;
+            } else if c == '"' {
+                in_string = false;
+            }
+            i += 1;
+            continue;
+        }
+
+        match (c, chars.get(i + 1)) {
+            ('"', _) => {
+                in_string = true;
+                stripped.push(c);
+                i += 1;
+            }
+            ('/', Some('/')) => {
+                while i < chars.len() && chars[i] != '\n' {
+                    stripped.push(' ');
+                    i += 1;
+                }
+            }
+            ('/', Some('*')) => {
+                let start = i;
+                while i < chars.len() && !(i >= start + 4 && chars[i - 2..i] == ['*', '/']) {
+                    stripped.push(if chars[i] == '\n' { '\n' } else { ' ' });
+                    i += 1;
+                }
+            }
+            (',', _) if closes_next(&chars[i + 1..]) => {
+                stripped.push(' ');
+                i += 1;
+            }
+            _ => {
+                stripped.push(c);
+                i += 1;
+            }
+        }
+    }
+    stripped
+}
+
+/// Whether the next token after whitespace and comments closes an object or array
+fn closes_next(rest: &[char]) -> bool {
+    let mut i = 0;
+    while i < rest.len() {
+        match (rest[i], rest.get(i + 1)) {
+            (c, _) if c.is_whitespace() => i += 1,
+            ('/', Some('/')) => {
+                while i < rest.len() && rest[i] != '\n' {
+                    i += 1;
+                }
+            }
+            ('/', Some('*')) => {
+                i += 3;
+                while i < rest.len() && !(rest[i - 1] == '*' && rest[i] == '/') {
+                    i += 1;
+                }
+                i += 1;
+            }
+            (c, _) => return c == '}' || c == ']',
+        }
+    }
+    false
+}
+
+/// Whether a YAML or TOML config has `#` comments, a `#` starts one at the start of a line or
+/// after whitespace outside of quotes
+fn has_hash_comments(text: &str) -> bool {
+    text.lines().any(|line| {
+        let mut quote = None;
+        let mut previous = ' ';
+        for c in line.chars() {
+            match (c, quote) {
+                ('"' | '\'', None) => quote = Some(c),
+                (c, Some(q)) if c == q && previous != '\\' => quote = None,
+                ('#', None) if previous.is_whitespace() => return true,
+                _ => {}
+            }
+            previous = c;
+        }
+        false
+    })
+}
+
+/// Rewrites the value starting at `start` in `text` from `old` to `new`, returning it and where
+/// the original ended. Objects are patched member by member, anything else that changed is
+/// written out again.
+fn update_value(text: &str, start: usize, old: &Value, new: &Value) -> Result<(String, usize)> {
+    let mut scanner = Scanner::new(text);
+    scanner.pos = start;
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => update_object(text, start, old, new),
+        _ => {
+            scanner.skip_value()?;
+            let updated = if old == new {
+                text[start..scanner.pos].to_string()
+            } else {
+                let indent = line_indent(text, start);
+                to_json(new).replace('\n', &format!("\n{}", indent))
+            };
+            Ok((updated, scanner.pos))
+        }
+    }
+}
+
+/// One `key: value` pair of an object, split so it can be written back as it was
+struct Member<'a> {
+    key: String,
+    /// Whitespace and comments on the lines before the key
+    leading: &'a str,
+    /// The key up to the start of the value
+    head: &'a str,
+    value: usize,
+    /// Whitespace and comments between the value and its comma
+    trailing: &'a str,
+    /// A comment after the comma on the same line
+    after: &'a str,
+}
+
+fn update_object(
+    text: &str,
+    start: usize,
+    old: &Map<String, Value>,
+    new: &Map<String, Value>,
+) -> Result<(String, usize)> {
+    let mut scanner = Scanner::new(text);
+    scanner.pos = start;
+    scanner.expect(b'{')?;
+
+    let mut members: Vec<Member> = vec![];
+    let mut trailing_comma = false;
+    let mut closing = loop {
+        let leading_start = scanner.pos;
+        scanner.skip_trivia();
+        let mut leading = &text[leading_start..scanner.pos];
+        // The rest of the line after a comma belongs to the member before it
+        if let (Some(previous), Some(line_end)) = (members.last_mut(), leading.find('\n')) {
+            previous.after = &leading[..line_end];
+            leading = &leading[line_end..];
+        }
+        if scanner.peek() == Some(b'}') {
+            trailing_comma = !members.is_empty();
+            break leading;
+        }
+        let key_start = scanner.pos;
+        let key = scanner.key()?;
+        scanner.skip_trivia();
+        scanner.expect(b':')?;
+        scanner.skip_trivia();
+        let value = scanner.pos;
+        scanner.skip_value()?;
+        let value_end = scanner.pos;
+        scanner.skip_trivia();
+        let mut member = Member {
+            key,
+            leading,
+            head: &text[key_start..value],
+            value,
+            trailing: "",
+            after: "",
+        };
+        if scanner.peek() == Some(b',') {
+            member.trailing = &text[value_end..scanner.pos];
+            scanner.pos += 1;
+            members.push(member);
+        } else {
+            members.push(member);
+            break &text[value_end..scanner.pos];
+        }
+    };
+    scanner.expect(b'}')?;
+    if let (false, Some(last), Some(line_end)) =
+        (trailing_comma, members.last_mut(), closing.find('\n'))
+    {
+        last.after = &closing[..line_end];
+        closing = &closing[line_end..];
+    }
+
+    let inline = !members.is_empty() && !text[start..scanner.pos].contains('\n');
+    let object_indent = line_indent(text, start);
+    let indent = members
+        .iter()
+        .filter_map(|m| m.leading.rsplit_once('\n'))
+        .map(|(_, indent)| indent)
+        .find(|indent| !indent.is_empty() && indent.trim().is_empty())
+        .map_or_else(|| format!("{}    ", object_indent), str::to_string);
+
+    let mut entries = vec![];
+    for (key, value) in new {
+        match (members.iter().find(|m| &m.key == key), old.get(key)) {
+            (Some(member), Some(old_value)) => {
+                let (value, _) = update_value(text, member.value, old_value, value)?;
+                let entry = format!(
+                    "{}{}{}{}",
+                    member.leading, member.head, value, member.trailing
+                );
+                entries.push((entry, member.after));
+            }
+            // Objects written on one line stay on one line
+            _ if inline => {
+                let value = serde_json::to_string(value)?;
+                entries.push((format!(" {}: {}", to_json(key), value), ""));
+            }
+            _ => {
+                let value = to_json(value).replace('\n', &format!("\n{}", indent));
+                entries.push((format!("\n{}{}: {}", indent, to_json(key), value), ""));
+            }
+        }
+    }
+
+    let mut updated = "{".to_string();
+    let count = entries.len();
+    for (i, (entry, after)) in entries.into_iter().enumerate() {
+        updated.push_str(&entry);
+        if i + 1 < count || trailing_comma {
+            updated.push(',');
+        }
+        updated.push_str(after);
+    }
+    if members.is_empty() && count > 0 {
+        updated.push_str(&format!("\n{}", object_indent));
+    } else {
+        updated.push_str(closing);
+    }
+    updated.push('}');
+    Ok((updated, scanner.pos))
+}
+
+fn to_json<T: Serialize>(value: &T) -> String {
+    Format::Json.to_string(value)
+}
+
+/// Indentation of the line `position` is on
+fn line_indent(text: &str, position: usize) -> &str {
+    let line_start = text[..position].rfind('\n').map_or(0, |i| i + 1);
+    let line = &text[line_start..position];
+    &line[..line.len() - line.trim_start().len()]
+}
+
+/// Walks commented JSON and JSON5 without building values, only tracking where things are
+struct Scanner<'a> {
+    text: &'a str,
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(text: &'a str) -> Scanner<'a> {
+        Scanner {
+            text,
+            bytes: text.as_bytes(),
+            pos: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<()> {
+        if self.peek() != Some(byte) {
+            return Err(Error::custom(format!(
+                "Expected '{}' at byte {}",
+                byte as char, self.pos
+            )));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// Skips whitespace and comments
+    fn skip_trivia(&mut self) {
+        loop {
+            match (self.peek(), self.bytes.get(self.pos + 1)) {
+                (Some(c), _) if c.is_ascii_whitespace() => self.pos += 1,
+                (Some(b'/'), Some(b'/')) => {
+                    while self.peek().is_some_and(|c| c != b'\n') {
+                        self.pos += 1;
+                    }
+                }
+                (Some(b'/'), Some(b'*')) => {
+                    self.pos = self.text[self.pos + 2..]
+                        .find("*/")
+                        .map_or(self.bytes.len(), |i| self.pos + 2 + i + 2);
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn skip_string(&mut self) -> Result<()> {
+        let quote = self.bytes[self.pos];
+        self.pos += 1;
+        while let Some(c) = self.peek() {
+            self.pos += if c == b'\\' { 2 } else { 1 };
+            if c == quote {
+                return Ok(());
+            }
+        }
+        Err(Error::custom("Unterminated string"))
+    }
+
+    /// Reads a quoted key, or a bare JSON5 identifier
+    fn key(&mut self) -> Result<String> {
+        let start = self.pos;
+        if let Some(b'"' | b'\'') = self.peek() {
+            self.skip_string()?;
+            return json5::from_str(&self.text[start..self.pos]).map_err(Error::custom);
+        }
+        while self
+            .peek()
+            .is_some_and(|c| !c.is_ascii_whitespace() && !b":/".contains(&c))
+        {
+            self.pos += 1;
+        }
+        Ok(self.text[start..self.pos].to_string())
+    }
+
+    fn skip_value(&mut self) -> Result<()> {
+        match self.peek() {
+            Some(b'"' | b'\'') => self.skip_string(),
+            Some(open @ (b'{' | b'[')) => {
+                let close = if open == b'{' { b'}' } else { b']' };
+                self.pos += 1;
+                loop {
+                    self.skip_trivia();
+                    match self.peek() {
+                        Some(c) if c == close => {
+                            self.pos += 1;
+                            return Ok(());
+                        }
+                        Some(b',' | b':') => self.pos += 1,
+                        Some(_) => self.skip_value()?,
+                        None => return Err(Error::custom("Unexpected end of config")),
+                    }
+                }
+            }
+            _ => {
+                let start = self.pos;
+                while self
+                    .peek()
+                    .is_some_and(|c| !c.is_ascii_whitespace() && !b",:]}/".contains(&c))
+                {
+                    self.pos += 1;
+                }
+                if self.pos == start {
+                    return Err(Error::custom(format!("Expected a value at byte {}", start)));
+                }
+                Ok(())
+            }
+        }
+    }
+}
@@ -1,11 +1,19 @@
 use std::time::SystemTime;
 
-use clap::Parser;
+use clap::{ArgEnum, Parser};
 
+mod animation;
 mod art;
 mod cmd;
 mod config;
+mod format;
 mod metadata;
+mod odds;
+mod output;
+mod provenance;
+mod simulate;
+mod svg;
+mod template;
 mod tests;
 
 /// Generative art program for Solana NFTs
@@ -18,11 +26,33 @@ struct Options {
 
 #[derive(Parser, Debug)]
 enum SubCommand {
+    Check(Check),
     Generate(Generate),
     Init(Init),
+    Migrate(Migrate),
+    Odds(Odds),
+    Schema(Schema),
+    Simulate(Simulate),
+    Sync(Sync),
     Verify(Verify),
 }
 
+/// Validate the config and layer files and print every weight table normalized
+#[derive(Parser, Debug)]
+pub struct Check {
+    /// Location of configuration file
+    #[clap(short, long, default_value = "./assets/config.json")]
+    config: String,
+
+    /// Location of assets whose layer files are checked
+    #[clap(short, long, default_value = "./assets")]
+    assets: String,
+
+    /// Override a config value, e.g. --set amount=500
+    #[clap(long, value_name = "KEY=VALUE", multiple_occurrences(true))]
+    set: Vec<String>,
+}
+
 /// Generate artwork and metadata
 #[derive(Parser, Debug)]
 pub struct Generate {
@@ -45,6 +75,22 @@ pub struct Generate {
     /// Ouput location of generated art
     #[clap(short, long, default_value = "./generated")]
     output: String,
+
+    /// Seed for random rolls, overrides the seed in the config
+    #[clap(long)]
+    seed: Option<u64>,
+
+    /// Override a config value, e.g. --set amount=500 or --set templates.name="{{name}}"
+    #[clap(long, value_name = "KEY=VALUE", multiple_occurrences(true))]
+    set: Vec<String>,
+
+    /// Format of generated images, overrides image.format in the config
+    #[clap(long, possible_values = ["png", "jpeg", "webp", "avif"])]
+    format: Option<String>,
+
+    /// Quality from 1 to 100 of JPEG, WebP and AVIF images, overrides image.quality
+    #[clap(long)]
+    quality: Option<u8>,
 }
 
 /// Initialize assets directory
@@ -63,6 +109,92 @@ pub struct Init {
     from_existing: Option<String>,
 }
 
+/// Upgrade a config written for an older version of resin
+#[derive(Parser, Debug)]
+pub struct Migrate {
+    /// Location of configuration file
+    #[clap(short, long, default_value = "./assets/config.json")]
+    config: String,
+
+    /// Print the changes without writing the config
+    #[clap(long)]
+    dry_run: bool,
+}
+
+/// Calculate the exact chance of each trait from the weights and keyed tables
+#[derive(Parser, Debug)]
+pub struct Odds {
+    /// Location of configuration file
+    #[clap(short, long, default_value = "./assets/config.json")]
+    config: String,
+
+    /// Chance of traits rolled together, e.g. --joint "face:gold-face.png&mouth:smile-mouth.png"
+    #[clap(long, value_name = "LAYER:TRAIT&...", multiple_occurrences(true))]
+    joint: Vec<String>,
+
+    /// Override a config value, e.g. --set amount=500
+    #[clap(long, value_name = "KEY=VALUE", multiple_occurrences(true))]
+    set: Vec<String>,
+}
+
+/// Print the JSON Schema of configs or generated metadata
+#[derive(Parser, Debug)]
+pub struct Schema {
+    /// Which schema to print
+    #[clap(arg_enum)]
+    kind: SchemaKind,
+
+    /// File to write the schema to instead of printing it
+    #[clap(short, long)]
+    output: Option<String>,
+}
+
+#[derive(ArgEnum, Clone, Debug)]
+pub enum SchemaKind {
+    Config,
+    Metadata,
+}
+
+/// Roll the collection many times in memory and report how often each trait comes up
+#[derive(Parser, Debug)]
+pub struct Simulate {
+    /// Location of configuration file
+    #[clap(short, long, default_value = "./assets/config.json")]
+    config: String,
+
+    /// Number of collections to roll
+    #[clap(long, default_value = "1000")]
+    runs: u32,
+
+    /// Seed for random rolls, a random seed is used if not set
+    #[clap(long)]
+    seed: Option<u64>,
+
+    /// Override a config value, e.g. --set amount=500
+    #[clap(long, value_name = "KEY=VALUE", multiple_occurrences(true))]
+    set: Vec<String>,
+}
+
+/// Add new asset files to the config and flag deleted ones
+#[derive(Parser, Debug)]
+pub struct Sync {
+    /// Location of assets to sync
+    #[clap(short, long, default_value = "./assets")]
+    assets: String,
+
+    /// Location of configuration file
+    #[clap(short, long, default_value = "./assets/config.json")]
+    config: String,
+
+    /// Remove entries for files that no longer exist instead of only listing them
+    #[clap(long)]
+    remove: bool,
+
+    /// Print the changes without writing the config
+    #[clap(long)]
+    dry_run: bool,
+}
+
 /// Verify generated assets integrity
 #[derive(Parser, Debug)]
 pub struct Verify {
@@ -73,14 +205,26 @@ pub struct Verify {
 
 fn main() {
     let options = Options::parse();
-    println!("Starting");
+    // Schemas are printed on their own so they can be piped into a file
+    let quiet = matches!(options.subcmd, SubCommand::Schema(_));
+    if !quiet {
+        println!("Starting");
+    }
     let now = SystemTime::now();
 
     match options.subcmd {
+        SubCommand::Check(c) => cmd::check::handle(c),
         SubCommand::Generate(c) => cmd::generate::handle(c),
         SubCommand::Init(c) => cmd::init::handle(c),
+        SubCommand::Migrate(c) => cmd::migrate::handle(c),
+        SubCommand::Odds(c) => cmd::odds::handle(c),
+        SubCommand::Schema(c) => cmd::schema::handle(c),
+        SubCommand::Simulate(c) => cmd::simulate::handle(c),
+        SubCommand::Sync(c) => cmd::sync::handle(c),
         SubCommand::Verify(c) => cmd::verify::handle(c),
     }
 
-    println!("Finished in {:#?}", now.elapsed().unwrap_or_default());
+    if !quiet {
+        println!("Finished in {:#?}", now.elapsed().unwrap_or_default());
+    }
 }
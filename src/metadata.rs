@@ -1,142 +1,389 @@
 use indexmap::IndexMap;
 use rand::distributions::WeightedIndex;
 use rand::prelude::*;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value};
 use std::{
+    collections::{BTreeMap, HashMap, HashSet},
     ffi::OsStr,
     fmt::Display,
-    fs::{create_dir_all, File},
+    fs::{copy, create_dir_all, File},
+    hash::{Hash, Hasher},
     io::Write,
-    path::Path,
+    path::{Path, PathBuf},
 };
 
-use crate::config::{self, Attribute};
+use crate::{
+    animation, art,
+    config::{self, Attribute, DisplayType, NamedPosition, OneOfOne, Position, Tier},
+    output, svg,
+    template::Template,
+};
 
-pub fn generate(config_location: &String, _assets_directory: &String, output_directory: &String) {
+pub fn generate(
+    config_location: &str,
+    assets_directory: &str,
+    output_directory: &str,
+    seed: Option<u64>,
+    sets: &[String],
+) {
     println!("Generating metadata...");
 
-    let config = config::parse(config_location.as_str()).expect("Error parsing config");
-
-    create_dir_all(Path::new(output_directory).join(".resin")).expect(&format!(
-        "Could not create output directory at {}",
-        output_directory
-    ));
-
-    // Collection of generated rolls used if `require_unique` is enabled
-    let mut generated_rolls: Vec<Vec<Trait>> = Vec::new();
-
-    let mut guaranteed_rolls = config.guaranteed_attribute_rolls.clone();
-    let attribute_names: Vec<&String> = config.attributes.keys().collect();
-    // How often to insert a guaranteed roll into generated rolls
-    let insert_frequency = config.amount / (guaranteed_rolls.len() as u32 + 1);
-    for i in 0..config.amount {
-        if i > 0 && guaranteed_rolls.len() > 0 && i % insert_frequency == 0 {
-            let roll_attributes = {
-                guaranteed_rolls[0]
-                    .iter()
-                    .enumerate()
-                    .map(|(i, t)| Trait {
-                        trait_type: attribute_names[i].clone(),
-                        value: t.to_string(),
-                    })
-                    .collect()
-            };
-            create_metadata(i, roll_attributes, &config, output_directory);
-            guaranteed_rolls.remove(0);
-        } else {
-            generate_attributes(i, &config, output_directory, &mut generated_rolls, None);
+    let tiers = config::parse_tiers(config_location, sets).expect("Error parsing config");
+    let base = &tiers[0].config;
+
+    let seed = seed.or(base.seed).unwrap_or_else(|| thread_rng().gen());
+    println!("Using seed {}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    for (tiers, directory) in config::collection_directories(&tiers, output_directory) {
+        generate_collection(tiers, assets_directory, &directory, seed, &mut rng);
+    }
+}
+
+/// Generates the metadata of every token in `tiers` into a single collection
+fn generate_collection(
+    tiers: &[Tier],
+    assets_directory: &str,
+    output_directory: &str,
+    seed: u64,
+    rng: &mut StdRng,
+) {
+    create_dir_all(Path::new(output_directory).join(".resin"))
+        .unwrap_or_else(|_| panic!("Could not create output directory at {}", output_directory));
+
+    let mut tokens: Vec<(&Tier, Token)> = vec![];
+    let mut pinned: HashSet<u32> = HashSet::new();
+    // Shared by every tier so `require_unique` holds across the whole collection
+    let mut generated_rolls: HashSet<Vec<Trait>> = HashSet::new();
+    for tier in tiers {
+        if let Some(name) = &tier.name {
+            println!("Generating tier {}...", name);
+        }
+        let tier_tokens = generate_tokens(&tier.config, &mut generated_rolls, rng);
+        let offset = tokens.len() as u32;
+        pinned.extend(pinned_ids(&tier.config, &tier_tokens).map(|id| offset + id));
+        tokens.extend(tier_tokens.into_iter().map(|t| (tier, t)));
+    }
+
+    // Final token ID for each generated token
+    let mut permutation: Vec<u32> = (0..tokens.len() as u32).collect();
+    if tiers[0].config.shuffle.unwrap_or_default() {
+        // Pinned tokens keep their ID, the rest are shuffled among the remaining IDs
+        let free: Vec<u32> = (0..tokens.len() as u32)
+            .filter(|id| !pinned.contains(id))
+            .collect();
+        let mut shuffled = free.clone();
+        shuffled.shuffle(rng);
+        for (from, to) in free.into_iter().zip(shuffled) {
+            permutation[from as usize] = to;
+        }
+        write_metadata(
+            "permutation",
+            &serde_json::to_string(&Permutation {
+                seed,
+                permutation: &permutation,
+            })
+            .expect("Could not serialize permutation JSON"),
+            &Path::new(output_directory).join(".resin").to_string_lossy(),
+        );
+    }
+
+    let mut one_of_ones = vec![];
+    let mut rarity: IndexMap<String, IndexMap<String, u32>> = IndexMap::new();
+    // Whether each layer file is animated, checking means decoding its frames
+    let mut animated: HashMap<PathBuf, bool> = HashMap::new();
+    for ((tier, token), id) in tokens.into_iter().zip(permutation) {
+        let public = match token {
+            Token::Rolled(attributes) => {
+                let attributes = with_numeric_attributes(attributes, &tier.config, rng);
+                create_metadata(
+                    id,
+                    attributes,
+                    tier,
+                    assets_directory,
+                    output_directory,
+                    &mut animated,
+                )
+            }
+            Token::OneOfOne(one_of_one) => {
+                one_of_ones.push(id);
+                create_one_of_one_metadata(id, one_of_one, &tier.config, output_directory)
+            }
+        };
+        // Numeric traits are left out, nearly every value would be unique
+        for t in public.iter().filter(|t| t.display_type.is_none()) {
+            if let Some(value) = t.value.as_str() {
+                *rarity
+                    .entry(t.trait_type.clone())
+                    .or_default()
+                    .entry(value.to_string())
+                    .or_default() += 1;
+            }
+        }
+    }
+    one_of_ones.sort();
+
+    create_collection_metadata(&tiers[0].config, one_of_ones, rarity, output_directory);
+}
+
+/// Every token of a config in ID order, before any shuffle
+///
+/// `generated_rolls` holds the rolls already in the collection, used if `require_unique` is
+/// enabled.
+pub fn generate_tokens<'a>(
+    config: &'a config::Config,
+    generated_rolls: &mut HashSet<Vec<Trait>>,
+    rng: &mut StdRng,
+) -> Vec<Token<'a>> {
+    let mut tokens: Vec<Option<Token>> = (0..config.amount).map(|_| None).collect();
+
+    let positions: Vec<Option<Position>> = config.positions().collect();
+    let ids = token_ids(&positions, config.amount, rng);
+    let (guaranteed_ids, one_of_one_ids) = ids.split_at(config.guaranteed_attribute_rolls.len());
+
+    for (one_of_one, id) in config.one_of_one_tokens.iter().zip(one_of_one_ids) {
+        tokens[*id as usize] = Some(Token::OneOfOne(one_of_one));
+    }
+
+    // Guaranteed rolls go first so random rolls can't take their trait combinations
+    for (roll, id) in config.guaranteed_attribute_rolls.iter().zip(guaranteed_ids) {
+        let fixed = roll
+            .traits(&config.attributes)
+            .expect("Guaranteed roll was not validated")
+            .into_iter()
+            .map(|(layer, value)| {
+                let value = resolve_file_name(config, &layer, &value);
+                (layer, value)
+            })
+            .collect();
+        tokens[*id as usize] = Some(Token::Rolled(generate_attributes(
+            config,
+            generated_rolls,
+            &fixed,
+            rng,
+            None,
+        )));
+    }
+
+    for token in tokens.iter_mut().filter(|t| t.is_none()) {
+        *token = Some(Token::Rolled(generate_attributes(
+            config,
+            generated_rolls,
+            &IndexMap::new(),
+            rng,
+            None,
+        )));
+    }
+
+    tokens
+        .into_iter()
+        .map(|t| t.expect("Every token should have been generated"))
+        .collect()
+}
+
+/// IDs of `tokens` that keep their place when shuffling, guaranteed rolls with a fixed ID
+/// and one of ones
+fn pinned_ids<'a>(
+    config: &'a config::Config,
+    tokens: &'a [Token],
+) -> impl Iterator<Item = u32> + 'a {
+    let fixed = config.positions().filter_map(|p| match p {
+        Some(Position::Id(id)) => Some(id),
+        _ => None,
+    });
+    let one_of_ones = tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| matches!(t, Token::OneOfOne(_)))
+        .map(|(id, _)| id as u32);
+    fixed.chain(one_of_ones)
+}
+
+pub enum Token<'a> {
+    Rolled(Vec<Trait>),
+    OneOfOne(&'a OneOfOne),
+}
+
+/// Record of a shuffle, `permutation[n]` is the final ID of the token generated as `n`
+#[derive(Serialize)]
+struct Permutation<'a> {
+    seed: u64,
+    permutation: &'a [u32],
+}
+
+/// Token ID for each requested position, positions that aren't set are spread evenly
+fn token_ids(positions: &[Option<Position>], amount: u32, rng: &mut StdRng) -> Vec<u32> {
+    let mut ids: Vec<Option<u32>> = vec![None; positions.len()];
+    let mut taken = vec![false; amount as usize];
+
+    for (i, position) in positions.iter().enumerate() {
+        if let Some(Position::Id(id)) = position {
+            ids[i] = Some(*id);
+            taken[*id as usize] = true;
+        }
+    }
+
+    let spread: Vec<usize> = (0..positions.len())
+        .filter(|i| {
+            matches!(
+                positions[*i],
+                None | Some(Position::Named(NamedPosition::Spread))
+            )
+        })
+        .collect();
+    for (n, i) in spread.iter().enumerate() {
+        // Evenly spaced, moving forward to the next free ID if this one is taken
+        let mut id = ((n as u64 + 1) * amount as u64 / (spread.len() as u64 + 1)) as u32;
+        while taken[id as usize] {
+            id = (id + 1) % amount;
+        }
+        ids[*i] = Some(id);
+        taken[id as usize] = true;
+    }
+
+    for (i, position) in positions.iter().enumerate() {
+        if let Some(Position::Named(NamedPosition::Random)) = position {
+            let free: Vec<u32> = (0..amount).filter(|id| !taken[*id as usize]).collect();
+            let id = *free.choose(rng).expect("No free IDs left to place token");
+            ids[i] = Some(id);
+            taken[id as usize] = true;
         }
     }
 
-    create_collection_metadata(&config, output_directory);
+    ids.into_iter()
+        .map(|id| id.expect("Token was not given an ID"))
+        .collect()
+}
+
+/// File name in a layer matching `value`, which may leave out the file extension
+fn resolve_file_name(config: &config::Config, layer: &str, value: &str) -> String {
+    let mut files = config.attributes[layer].iter().flat_map(|(k, a)| match a {
+        Attribute::Keyed(a) => a.keys().collect::<Vec<&String>>(),
+        Attribute::Standard(_) => vec![k],
+    });
+    match files.find(|f| f.as_str() == value || stylize_asset_name(f) == value) {
+        Some(file) => file.to_string(),
+        None => {
+            println!(
+                "Warning: guaranteed trait {} is not in any table of layer {}",
+                value, layer
+            );
+            value.to_string()
+        }
+    }
 }
 
 fn generate_attributes(
-    n: u32,
     config: &config::Config,
-    output_directory: &String,
-    generated_rolls: &mut Vec<Vec<Trait>>,
+    generated_rolls: &mut HashSet<Vec<Trait>>,
+    fixed: &IndexMap<String, String>,
+    rng: &mut StdRng,
     retries: Option<u32>,
-) {
+) -> Vec<Trait> {
     let retries = retries.unwrap_or_default();
     let mut attributes = Vec::new();
-    let mut rng = thread_rng();
 
     for (attribute_name, keys) in &config.attributes {
-        let mut subattribute: IndexMap<String, f32> = IndexMap::new();
+        if let Some(value) = fixed.get(attribute_name) {
+            attributes.push(Trait::new(attribute_name, value.as_str()));
+            continue;
+        }
 
-        for (raw_key, a) in keys {
-            match a {
-                Attribute::Keyed(a) => {
-                    if raw_key == "_" {
-                        continue;
-                    }
+        let subattribute = select_table(keys, &attributes);
 
-                    let mut good_match = raw_key.split("&").all(|k| {
-                        let (key, value) = k.trim().split_once(":").unwrap_or(("_key", k));
+        if let Some(p) = config
+            .layers
+            .get(attribute_name)
+            .and_then(|l| l.none_probability)
+        {
+            // Layer is omitted from this roll
+            if rng.gen_bool(p as f64) {
+                continue;
+            }
+        }
 
-                        attributes.iter().any(|t: &Trait| {
-                            t.trait_type == key && stylize_asset_name(&t.value) == value
-                        })
-                    });
+        calculate_rng_for_attribute(attribute_name, &subattribute, &mut attributes, rng);
+    }
 
-                    if !good_match {
-                        good_match = raw_key.split("|").any(|k| {
-                            let (key, value) = k.trim().split_once(":").unwrap_or(("_key", k));
+    // Fully specified guaranteed rolls are kept even if they repeat
+    let is_random = fixed.len() < config.attributes.len();
+    if is_random
+        && config.require_unique.unwrap_or_default()
+        && generated_rolls.contains(&attributes)
+    {
+        if retries > config.max_retries.unwrap_or(64) {
+            panic!(
+                "Exceeded retry count to ensure uniqueness. Your config may need more attributes."
+            )
+        }
+        // If it already exists, re-roll
+        return generate_attributes(config, generated_rolls, fixed, rng, Some(retries + 1));
+    }
 
-                            attributes.iter().any(|t: &Trait| {
-                                t.trait_type == key && stylize_asset_name(&t.value) == value
-                            })
-                        });
-                    }
+    generated_rolls.insert(attributes.clone());
+    attributes
+}
 
-                    if good_match {
-                        subattribute = a.clone();
-                        break;
-                    }
+/// Weights a layer rolls from, given the traits rolled so far
+///
+/// The first keyed table, in key order, whose condition matches is used, otherwise the `"_"`
+/// table together with any plain weights.
+pub fn select_table(keys: &BTreeMap<String, Attribute>, rolled: &[Trait]) -> IndexMap<String, f32> {
+    let mut subattribute: IndexMap<String, f32> = IndexMap::new();
+
+    for (raw_key, a) in keys {
+        match a {
+            Attribute::Keyed(a) => {
+                if raw_key == "_" {
+                    continue;
                 }
-                Attribute::Standard(_) => continue,
-            }
-        }
 
-        if subattribute.is_empty() {
-            for (k, a) in keys {
-                match a {
-                    Attribute::Keyed(_) => continue,
-                    Attribute::Standard(v) => subattribute.insert(k.to_string(), *v),
+                let matches = |k: &str| {
+                    let (key, value) = condition(k);
+                    rolled.iter().any(|t: &Trait| t.is(key, value))
                 };
+                let good_match = raw_key.split('&').all(matches) || raw_key.split('|').any(matches);
+
+                if good_match {
+                    subattribute = a.clone();
+                    break;
+                }
             }
+            Attribute::Standard(_) => continue,
         }
-
-        calculate_rng_for_attribute(attribute_name, &subattribute, &mut attributes, &mut rng);
     }
 
-    if config.require_unique.unwrap_or_default() && generated_rolls.contains(&attributes) {
-        if retries > config.max_retries.unwrap_or(64) {
-            panic!(
-                "Exceeded retry count to ensure uniqueness. Your config may need more attributes."
-            )
+    if subattribute.is_empty() {
+        for (k, a) in keys {
+            match a {
+                Attribute::Keyed(a) => {
+                    if k == "_" {
+                        subattribute.extend(a.iter().map(|(k, v)| (k.to_string(), *v)));
+                    }
+                }
+                Attribute::Standard(v) => {
+                    subattribute.insert(k.to_string(), *v);
+                }
+            };
         }
-        // If it already exists, re-roll
-        return generate_attributes(
-            n,
-            config,
-            output_directory,
-            generated_rolls,
-            Some(retries + 1),
-        );
     }
 
-    generated_rolls.push(attributes.clone());
+    subattribute
+}
 
-    create_metadata(n, attributes, config, output_directory)
+/// Layer and value of one part of a table key, plain keys like `joker` refer to `_key`
+pub fn condition(part: &str) -> (&str, &str) {
+    let part = part.trim();
+    part.split_once(':').unwrap_or(("_key", part))
 }
 
 fn calculate_rng_for_attribute(
-    attribute_name: &String,
+    attribute_name: &str,
     attribute: &IndexMap<String, f32>,
     attributes: &mut Vec<Trait>,
-    rng: &mut ThreadRng,
+    rng: &mut StdRng,
 ) {
     let choices: Vec<&String> = attribute.keys().collect();
     let weights: Vec<&f32> = attribute.values().collect();
@@ -146,63 +393,345 @@ fn calculate_rng_for_attribute(
 
     let result = dist.sample(rng);
 
-    attributes.push(Trait {
-        trait_type: attribute_name.to_string(),
-        value: choices[result].to_string(),
-    });
+    attributes.push(Trait::new(attribute_name, choices[result].as_str()));
+}
+
+/// Appends the configured numeric attributes, rolled after layers so they can derive from them
+fn with_numeric_attributes(
+    mut attributes: Vec<Trait>,
+    config: &config::Config,
+    rng: &mut StdRng,
+) -> Vec<Trait> {
+    for (trait_type, numeric) in &config.numeric_attributes {
+        let mut value = match (&numeric.min, &numeric.max) {
+            (Some(min), Some(max)) => match (min.as_i64(), max.as_i64()) {
+                (Some(min), Some(max)) => Number::from(rng.gen_range(min..=max)),
+                _ => float_number(rng.gen_range(
+                    min.as_f64().unwrap_or_default()..=max.as_f64().unwrap_or_default(),
+                )),
+            },
+            _ => numeric.value.clone().unwrap_or_else(|| Number::from(0)),
+        };
+
+        for (attribute_name, contributions) in &numeric.from {
+            let contribution = attributes
+                .iter()
+                .find(|t| &t.trait_type == attribute_name)
+                .and_then(|t| t.value.as_str())
+                .and_then(|v| {
+                    contributions
+                        .get(v)
+                        .or_else(|| contributions.get(stylize_asset_name(v)))
+                })
+                .or_else(|| contributions.get("_"));
+            if let Some(contribution) = contribution {
+                value = add_numbers(&value, contribution);
+            }
+        }
+
+        attributes.push(Trait {
+            trait_type: trait_type.to_string(),
+            value: Value::Number(value),
+            display_type: numeric.display_type,
+            max_value: numeric.max_value.clone(),
+        });
+    }
+    attributes
+}
+
+fn add_numbers(a: &Number, b: &Number) -> Number {
+    // Sums of whole numbers past the range of i64 are kept as floats
+    match a
+        .as_i64()
+        .zip(b.as_i64())
+        .and_then(|(a, b)| a.checked_add(b))
+    {
+        Some(sum) => Number::from(sum),
+        None => float_number(a.as_f64().unwrap_or_default() + b.as_f64().unwrap_or_default()),
+    }
+}
+
+fn float_number(n: f64) -> Number {
+    Number::from_f64(n).expect("Numeric attribute is not a finite number")
 }
 
 fn create_metadata(
     id: u32,
     attributes: Vec<Trait>,
-    config: &config::Config,
-    output_directory: &String,
-) {
+    tier: &Tier,
+    assets_directory: &str,
+    output_directory: &str,
+    animated: &mut HashMap<PathBuf, bool>,
+) -> Vec<Trait> {
+    let config = &tier.config;
     let resin_metadata_directory = Path::new(output_directory).join(".resin");
-    let image_name = &format!("{}.png", id);
+    let mut files = output::files(id, &config.image);
+    let mut animation_url = None;
+    let mut category = "image";
+
+    let layer_paths = art::layer_paths(&attributes, config, assets_directory);
+    if !layer_paths.is_empty() && layer_paths.iter().all(|p| svg::is_svg(p)) {
+        if !config.svg.output.png() {
+            files.clear();
+        }
+        if config.svg.output.svg() {
+            files.push((format!("{}.svg", id), "image/svg+xml"));
+        }
+    } else if layer_paths.iter().any(|p| {
+        *animated
+            .entry(p.clone())
+            .or_insert_with(|| animation::is_animated(p))
+    }) {
+        let format = config.animation.format;
+        let animation_name = format!("{}.{}", id, format.extension());
+        files = vec![(animation_name.clone(), format.mime_type())];
+        files.extend(output::variant_files(id, &config.image));
+        animation_url = Some(animation_name);
+        if config.animation.mp4 {
+            let video_name = format!("{}.mp4", id);
+            files.push((video_name.clone(), "video/mp4"));
+            animation_url = Some(video_name);
+            category = "video";
+        }
+    }
+    let image_name = files[0].0.clone();
+
+    let values = template_values(id, &attributes, config);
+    let token_override = config.token_overrides.get(&id);
+    let render = |template: &String, token_override: Option<&String>| {
+        Template::parse(token_override.unwrap_or(template))
+            .expect("Template was not validated")
+            .render(&values)
+    };
+    let name = render(
+        &config.templates.name,
+        token_override.and_then(|o| o.name.as_ref()),
+    );
+    let description = render(
+        &config.templates.description,
+        token_override.and_then(|o| o.description.as_ref()),
+    );
+    let external_url = render(
+        &config.templates.external_url,
+        token_override.and_then(|o| o.external_url.as_ref()),
+    );
+
     let mut generated_metadata = NFTMetadata {
-        name: &format!("{} #{}", &config.name, id),
+        name: &name,
         symbol: &config.symbol,
-        description: &config.description,
+        description: &description,
+        image: &image_name,
+        animation_url: animation_url.as_deref(),
+        external_url: &external_url,
+        attributes: public_attributes(&attributes, config),
+        properties: Properties {
+            files: files
+                .iter()
+                .map(|(uri, r#type)| PropertyFile { uri, r#type })
+                .collect(),
+            category,
+        },
+        tier: None,
+    };
+    write_metadata(
+        id,
+        &serde_json::to_string(&generated_metadata).expect("Could not serialize generated JSON"),
+        output_directory,
+    );
+
+    let public = std::mem::replace(&mut generated_metadata.attributes, attributes);
+    generated_metadata.tier = tier.name.clone();
+    write_metadata(
+        id,
+        &serde_json::to_string(&generated_metadata).expect("Could not serialize generated JSON"),
+        &resin_metadata_directory.to_string_lossy(),
+    );
+    public
+}
+
+/// Copies a hand-made image into the collection and writes its metadata, returning its
+/// attributes
+fn create_one_of_one_metadata(
+    id: u32,
+    one_of_one: &OneOfOne,
+    config: &config::Config,
+    output_directory: &str,
+) -> Vec<Trait> {
+    let extension = one_of_one
+        .image
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("png");
+    let image_name = &format!("{}.{}", id, extension);
+    let image_path = Path::new(output_directory).join(image_name);
+    copy(&one_of_one.image, &image_path).unwrap_or_else(|_| {
+        panic!(
+            "Could not copy {} to {}",
+            one_of_one.image.display(),
+            image_path.display()
+        )
+    });
+
+    let values = template_values(id, &[], config);
+    let render = |template: &String, one_of_one_template: Option<&String>| {
+        Template::parse(one_of_one_template.unwrap_or(template))
+            .expect("Template was not validated")
+            .render(&values)
+    };
+    let name = render(&config.templates.name, one_of_one.name.as_ref());
+    let description = render(
+        &config.templates.description,
+        one_of_one.description.as_ref(),
+    );
+    let external_url = render(
+        &config.templates.external_url,
+        one_of_one.external_url.as_ref(),
+    );
+
+    let generated_metadata = NFTMetadata {
+        name: &name,
+        symbol: &config.symbol,
+        description: &description,
         image: image_name,
-        external_url: &config.external_url,
-        attributes: attributes
-            .to_vec()
-            .drain(..)
-            .filter(|attribute| !attribute.trait_type.starts_with("_"))
-            .map(|mut attribute| {
-                attribute.value = stylize_asset_name(&attribute.value).to_string();
-                attribute
-            })
-            .collect(),
+        animation_url: None,
+        external_url: &external_url,
+        attributes: one_of_one.attributes.clone(),
         properties: Properties {
             files: vec![PropertyFile {
                 uri: image_name,
-                r#type: "image/png",
+                r#type: mime_type(extension),
             }],
             category: "image",
         },
+        tier: None,
     };
+    // Not written to .resin so it is left out of compositing
     write_metadata(
         id,
         &serde_json::to_string(&generated_metadata).expect("Could not serialize generated JSON"),
         output_directory,
     );
+    generated_metadata.attributes
+}
 
-    generated_metadata.attributes = attributes;
-    write_metadata(
-        id,
-        &serde_json::to_string(&generated_metadata).expect("Could not serialize generated JSON"),
-        &resin_metadata_directory.to_string_lossy().to_string(),
+/// MIME type of a file extension, used for `properties.files`
+pub fn mime_type(extension: &str) -> &'static str {
+    match extension.to_lowercase().as_str() {
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "svg" => "image/svg+xml",
+        "mp4" => "video/mp4",
+        _ => "image/png",
+    }
+}
+
+/// Variables available to name, description and external URL templates
+fn template_values(
+    id: u32,
+    attributes: &[Trait],
+    config: &config::Config,
+) -> HashMap<String, String> {
+    let mut values = HashMap::from([
+        ("name".to_string(), config.name.clone()),
+        ("collectionName".to_string(), config.collection_name.clone()),
+        ("symbol".to_string(), config.symbol.clone()),
+        ("description".to_string(), config.description.clone()),
+        ("externalUrl".to_string(), config.external_url.clone()),
+        ("id".to_string(), id.to_string()),
+        ("edition".to_string(), (id + 1).to_string()),
+        ("amount".to_string(), config.amount.to_string()),
+        ("tier".to_string(), config.tier.clone().unwrap_or_default()),
+    ]);
+
+    for attribute_name in config.attributes.keys() {
+        let rolled = attributes.iter().find(|t| &t.trait_type == attribute_name);
+        if let Some(value) = display_value(attribute_name, rolled, config) {
+            values.insert(format!("trait.{}", attribute_name), value);
+        }
+        let text = rolled.and_then(|t| t.value.as_str()).and_then(|v| {
+            config
+                .layers
+                .get(attribute_name)?
+                .traits
+                .get(v)?
+                .text
+                .clone()
+        });
+        if let Some(text) = text {
+            values.insert(format!("text.{}", attribute_name), text);
+        }
+    }
+    for t in attributes {
+        if config.numeric_attributes.contains_key(&t.trait_type) {
+            values.insert(format!("trait.{}", t.trait_type), t.value.to_string());
+        }
+    }
+    values
+}
+
+/// Metadata value of a layer, `None` if the layer was omitted and there is no `none_value`
+fn display_value(
+    attribute_name: &str,
+    rolled: Option<&Trait>,
+    config: &config::Config,
+) -> Option<String> {
+    match rolled {
+        Some(t) => {
+            let file_name = t.value.as_str().unwrap_or_default();
+            let value = config
+                .layers
+                .get(attribute_name)
+                .and_then(|l| l.traits.get(file_name))
+                .and_then(|t| t.display_name.clone())
+                .unwrap_or_else(|| config.name_style.apply(stylize_asset_name(file_name)));
+            Some(value)
+        }
+        None => config.none_value.clone(),
+    }
+}
+
+/// Attributes as they appear in published metadata, in layer order with hidden layers removed
+fn public_attributes(attributes: &[Trait], config: &config::Config) -> Vec<Trait> {
+    let mut public = vec![];
+    for attribute_name in config.attributes.keys() {
+        if attribute_name.starts_with('_') {
+            continue;
+        }
+        let trait_type = config
+            .layers
+            .get(attribute_name)
+            .and_then(|l| l.display_name.clone())
+            .unwrap_or_else(|| config.name_style.apply(attribute_name));
+
+        let rolled = attributes.iter().find(|t| &t.trait_type == attribute_name);
+        if let Some(value) = display_value(attribute_name, rolled, config) {
+            public.push(Trait::new(&trait_type, value));
+        }
+    }
+    public.extend(
+        attributes
+            .iter()
+            .filter(|t| config.numeric_attributes.contains_key(&t.trait_type))
+            .cloned(),
     );
+    public
 }
 
-fn create_collection_metadata(config: &config::Config, output_directory: &String) {
-    let generated_metadata = NFTMetadata {
+/// Writes `collection.json`, listing the one of ones and how often every trait occurs
+fn create_collection_metadata(
+    config: &config::Config,
+    one_of_ones: Vec<u32>,
+    rarity: IndexMap<String, IndexMap<String, u32>>,
+    output_directory: &str,
+) {
+    let metadata = NFTMetadata {
         name: &config.collection_name,
         symbol: &config.symbol,
         description: &config.description,
         image: "collection.png",
+        animation_url: None,
         external_url: &config.external_url,
         attributes: vec![],
         properties: Properties {
@@ -212,6 +741,12 @@ fn create_collection_metadata(config: &config::Config, output_directory: &String
             }],
             category: "image",
         },
+        tier: None,
+    };
+    let generated_metadata = CollectionMetadata {
+        metadata,
+        one_of_ones,
+        rarity,
     };
     write_metadata(
         "collection",
@@ -220,17 +755,13 @@ fn create_collection_metadata(config: &config::Config, output_directory: &String
     );
 }
 
-fn write_metadata<T: Display>(id: T, data: &str, output_directory: &String) {
+fn write_metadata<T: Display>(id: T, data: &str, output_directory: &str) {
     let path_buffer = Path::new(output_directory).join(format!("{}.json", id));
 
-    let mut file = File::create(&path_buffer).expect(&format!(
-        "Could not create file at path {}",
-        path_buffer.display()
-    ));
-    write!(file, "{}", data).expect(&format!(
-        "Could not write to file at path {}",
-        path_buffer.display()
-    ));
+    let mut file = File::create(&path_buffer)
+        .unwrap_or_else(|_| panic!("Could not create file at path {}", path_buffer.display()));
+    write!(file, "{}", data)
+        .unwrap_or_else(|_| panic!("Could not write to file at path {}", path_buffer.display()));
 }
 
 fn stylize_asset_name(original: &str) -> &str {
@@ -241,30 +772,82 @@ fn stylize_asset_name(original: &str) -> &str {
         .unwrap_or(original)
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct NFTMetadata<'a> {
     name: &'a str,
     symbol: &'a str,
     description: &'a str,
     image: &'a str,
+    /// Animated or video version of the image, for tokens with animated layers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    animation_url: Option<&'a str>,
     external_url: &'a str,
     pub attributes: Vec<Trait>,
     properties: Properties<'a>,
+    /// Tier the token was generated from, only kept in `.resin` for compositing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(skip)]
+    pub tier: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize)]
+struct CollectionMetadata<'a> {
+    #[serde(flatten)]
+    metadata: NFTMetadata<'a>,
+    /// IDs of the hand-made one of ones
+    one_of_ones: Vec<u32>,
+    /// Number of tokens with each trait, keyed by trait type and then value
+    rarity: IndexMap<String, IndexMap<String, u32>>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
 pub struct Trait {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_type: Option<DisplayType>,
     pub trait_type: String,
-    pub value: String,
+    pub value: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_value: Option<Number>,
+}
+
+// Hashed through the JSON text of values, trait values are strings and numbers which print
+// the same whenever they are equal
+impl Hash for Trait {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.display_type.hash(state);
+        self.trait_type.hash(state);
+        self.value.to_string().hash(state);
+        self.max_value.as_ref().map(Number::to_string).hash(state);
+    }
+}
+
+impl Trait {
+    pub fn new(trait_type: &str, value: impl Into<Value>) -> Self {
+        Trait {
+            display_type: None,
+            trait_type: trait_type.to_string(),
+            value: value.into(),
+            max_value: None,
+        }
+    }
+
+    /// Whether this is the given layer with a value matching `name`, with or without extension
+    pub fn is(&self, trait_type: &str, name: &str) -> bool {
+        self.trait_type == trait_type
+            && self
+                .value
+                .as_str()
+                .is_some_and(|v| stylize_asset_name(v) == name)
+    }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 struct Properties<'a> {
     files: Vec<PropertyFile<'a>>,
     category: &'a str,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 struct PropertyFile<'a> {
     uri: &'a str,
     r#type: &'a str,
@@ -0,0 +1,114 @@
+use indexmap::{IndexMap, IndexSet};
+use std::collections::HashMap;
+
+use crate::{
+    config::Config,
+    metadata::{self, Trait},
+    simulate::NONE_TRAIT,
+};
+
+/// Exact probabilities of a single random roll, before uniqueness re-rolls
+pub struct Odds {
+    /// Probability of every trait of every layer, `NONE_TRAIT` for omitted layers
+    pub layers: IndexMap<String, IndexMap<String, f64>>,
+    /// Rolled traits of the layers other layers depend on, or that are in a combination
+    outcomes: HashMap<Vec<Trait>, f64>,
+}
+
+/// Traits that must all be rolled together, e.g. `face:gold-face.png&mouth:smile-mouth.png`
+pub type Combination = Vec<(String, String)>;
+
+pub fn parse_combination(source: &str, config: &Config) -> Result<Combination, String> {
+    source
+        .split('&')
+        .map(|part| {
+            let (layer, value) = part
+                .trim()
+                .split_once(':')
+                .ok_or_else(|| format!("Expected LAYER:TRAIT in {}", source))?;
+            if !config.attributes.contains_key(layer) {
+                return Err(format!("Layer {} in {} does not exist", layer, source));
+            }
+            Ok((layer.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Walks every branch of the keyed tables, keeping only the traits later layers depend on
+pub fn odds(config: &Config, combinations: &[Combination]) -> Odds {
+    let mut tracked: IndexSet<&str> = combinations
+        .iter()
+        .flatten()
+        .map(|(layer, _)| layer.as_str())
+        .collect();
+    for tables in config.attributes.values() {
+        for key in tables.keys().filter(|k| *k != "_") {
+            for part in key.split(['&', '|']) {
+                tracked.insert(metadata::condition(part).0);
+            }
+        }
+    }
+
+    let mut layers = IndexMap::new();
+    let mut outcomes: HashMap<Vec<Trait>, f64> = HashMap::from([(vec![], 1.0)]);
+    for (layer_name, tables) in &config.attributes {
+        let none_probability = config
+            .layers
+            .get(layer_name)
+            .and_then(|l| l.none_probability)
+            .unwrap_or_default() as f64;
+
+        let mut probabilities: IndexMap<String, f64> = IndexMap::new();
+        let mut next: HashMap<Vec<Trait>, f64> = HashMap::new();
+        for (rolled, p) in outcomes {
+            let table = metadata::select_table(tables, &rolled);
+            let total: f64 = table.values().map(|w| *w as f64).sum();
+            if none_probability > 0.0 {
+                *probabilities.entry(NONE_TRAIT.to_string()).or_default() += p * none_probability;
+            }
+
+            if !tracked.contains(layer_name.as_str()) || total <= 0.0 {
+                for (file, weight) in &table {
+                    *probabilities.entry(file.clone()).or_default() +=
+                        p * (1.0 - none_probability) * *weight as f64 / total;
+                }
+                *next.entry(rolled).or_default() += p;
+                continue;
+            }
+
+            if none_probability > 0.0 {
+                *next.entry(rolled.clone()).or_default() += p * none_probability;
+            }
+            for (file, weight) in &table {
+                let branch = p * (1.0 - none_probability) * *weight as f64 / total;
+                *probabilities.entry(file.clone()).or_default() += branch;
+                let mut with_trait = rolled.clone();
+                with_trait.push(Trait::new(layer_name, file.as_str()));
+                *next.entry(with_trait).or_default() += branch;
+            }
+        }
+        outcomes = next;
+        layers.insert(layer_name.clone(), probabilities);
+    }
+
+    Odds { layers, outcomes }
+}
+
+impl Odds {
+    /// Probability that every trait of `combination` is rolled together
+    pub fn joint(&self, combination: &Combination) -> f64 {
+        self.outcomes
+            .iter()
+            .filter(|(rolled, _)| {
+                combination.iter().all(|(layer, value)| {
+                    rolled
+                        .iter()
+                        .any(|t| t.trait_type == *layer && t.value.as_str() == Some(value.as_str()))
+                        || rolled.iter().any(|t| t.is(layer, value))
+                        || (value == NONE_TRAIT && !rolled.iter().any(|t| &t.trait_type == layer))
+                })
+            })
+            .map(|(_, p)| p)
+            .sum()
+    }
+}
@@ -0,0 +1,162 @@
+use image::{
+    codecs::{avif::AvifEncoder, jpeg::JpegEncoder},
+    imageops::{self, FilterType},
+    DynamicImage, ImageEncoder, ImageFormat as Codec, RgbaImage,
+};
+use std::{
+    fs::{self, remove_file},
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use crate::config::{ImageFormat, ImageOutput, ImageSettings, ResizeFilter};
+
+/// Quality of JPEG, WebP and AVIF images without one set
+pub const DEFAULT_QUALITY: u8 = 90;
+
+/// Name and MIME type of every file written for a token's image, the full-size image first
+/// and then every variant
+pub fn files(id: impl std::fmt::Display, output: &ImageOutput) -> Vec<(String, &'static str)> {
+    let format = output.settings.format();
+    let mut files = vec![(format!("{}.{}", id, format.extension()), format.mime_type())];
+    files.extend(variant_files(id, output));
+    files
+}
+
+/// Name and MIME type of every variant of a token's image
+pub fn variant_files(
+    id: impl std::fmt::Display,
+    output: &ImageOutput,
+) -> Vec<(String, &'static str)> {
+    output
+        .variants
+        .iter()
+        .map(|(name, settings)| {
+            let format = settings.or(&output.settings).format();
+            (
+                format!("{}-{}.{}", id, name, format.extension()),
+                format.mime_type(),
+            )
+        })
+        .collect()
+}
+
+/// Re-encodes the composited PNG at `path` with the configured settings and writes its
+/// variants, PNGs are left as they are by default
+pub fn finish(path: &Path, output: &ImageOutput) {
+    if output.is_default() {
+        return;
+    }
+    let image = image::open(path)
+        .unwrap_or_else(|e| panic!("Could not read image {}: {}", path.display(), e))
+        .into_rgba8();
+    if output.settings != ImageSettings::default() {
+        remove_file(path).unwrap_or_else(|_| panic!("Could not remove {}", path.display()));
+        save(&image, &output.settings, path);
+    }
+    save_variants(&image, output, path);
+}
+
+/// Writes every variant of the full-size `image` next to `path`, e.g. `5-thumbnail.webp`
+pub fn save_variants(image: &RgbaImage, output: &ImageOutput, path: &Path) {
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or_else(|| panic!("Image is not valid path at {}", path.display()));
+    for (name, settings) in &output.variants {
+        let variant_path = path.with_file_name(format!("{}-{}", stem, name));
+        save(image, &settings.or(&output.settings), &variant_path);
+    }
+}
+
+/// Resizes and encodes `image` to `path`, whose extension is replaced by the format's
+pub fn save(image: &RgbaImage, settings: &ImageSettings, path: &Path) {
+    let image = resize(image, settings);
+    let format = settings.format();
+    let path: PathBuf = path.with_extension(format.extension());
+    let quality = settings.quality.unwrap_or(DEFAULT_QUALITY);
+    let (width, height) = image.dimensions();
+
+    let mut data = vec![];
+    let encoded = match format {
+        ImageFormat::Png => DynamicImage::ImageRgba8(image)
+            .write_to(&mut Cursor::new(&mut data), Codec::Png)
+            .map_err(|e| e.to_string()),
+        // JPEG has no transparency, the alpha channel is dropped
+        ImageFormat::Jpeg => JpegEncoder::new_with_quality(&mut data, quality)
+            .encode_image(&DynamicImage::ImageRgba8(image).into_rgb8())
+            .map_err(|e| e.to_string()),
+        ImageFormat::Webp => {
+            data = webp::Encoder::from_rgba(&image, width, height)
+                .encode(quality as f32)
+                .to_vec();
+            Ok(())
+        }
+        ImageFormat::Avif => AvifEncoder::new_with_speed_quality(&mut data, 6, quality)
+            .write_image(&image, width, height, image::ExtendedColorType::Rgba8)
+            .map_err(|e| e.to_string()),
+    };
+    encoded.unwrap_or_else(|e| panic!("Could not encode {}: {}", path.display(), e));
+
+    if let (ImageFormat::Png, Some(level)) = (format, settings.png_optimization) {
+        data = oxipng::optimize_from_memory(&data, &oxipng::Options::from_preset(level))
+            .unwrap_or_else(|e| panic!("Could not optimize {}: {}", path.display(), e));
+    }
+    fs::write(&path, data).unwrap_or_else(|_| panic!("Could not write {}", path.display()));
+}
+
+/// Size of a `width`x`height` image upscaled by `scale`, if its RGBA buffer can be allocated
+pub fn scaled_size((width, height): (u32, u32), scale: u32) -> Option<(u32, u32)> {
+    let width = width.checked_mul(scale)?;
+    let height = height.checked_mul(scale)?;
+    (width as usize)
+        .checked_mul(height as usize)?
+        .checked_mul(4)
+        .filter(|bytes| *bytes <= isize::MAX as usize)?;
+    Some((width, height))
+}
+
+/// `image` upscaled by `scale`, then at the configured size, keeping the aspect ratio if only
+/// width or height is set
+pub fn resize(image: &RgbaImage, settings: &ImageSettings) -> RgbaImage {
+    let scaled;
+    let image = match settings.scale {
+        Some(scale) if scale > 1 => {
+            let (width, height) = scaled_size(image.dimensions(), scale).unwrap_or_else(|| {
+                panic!(
+                    "A {}x{} image is too large to scale by {}",
+                    image.width(),
+                    image.height(),
+                    scale
+                )
+            });
+            scaled = imageops::resize(image, width, height, FilterType::Nearest);
+            &scaled
+        }
+        _ => image,
+    };
+    let (width, height) = image.dimensions();
+    let (target_width, target_height) = match (settings.width, settings.height) {
+        (None, None) => return image.clone(),
+        (Some(w), Some(h)) => (w, h),
+        (Some(w), None) => (
+            w,
+            (w as f64 * height as f64 / width as f64).round().max(1.0) as u32,
+        ),
+        (None, Some(h)) => (
+            (h as f64 * width as f64 / height as f64).round().max(1.0) as u32,
+            h,
+        ),
+    };
+    if (target_width, target_height) == (width, height) {
+        return image.clone();
+    }
+    let filter = match settings.filter.unwrap_or_default() {
+        ResizeFilter::Nearest => FilterType::Nearest,
+        ResizeFilter::Triangle => FilterType::Triangle,
+        ResizeFilter::CatmullRom => FilterType::CatmullRom,
+        ResizeFilter::Gaussian => FilterType::Gaussian,
+        ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+    };
+    imageops::resize(image, target_width, target_height, filter)
+}
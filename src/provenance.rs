@@ -0,0 +1,198 @@
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{create_dir_all, read, read_dir, read_to_string, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Hashes of every generated file, written next to them as `manifest.json`
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    /// Hash of every image hash concatenated in token ID order
+    pub provenance_hash: String,
+    /// SHA-256 of each file keyed by file name, in token ID order
+    pub files: IndexMap<String, String>,
+}
+
+/// Writes the manifest of `output_directory`, and the provenance record to `.resin` if
+/// `provenance` is set
+pub fn generate(output_directory: &str, provenance: bool) {
+    println!("Generating manifest...");
+
+    let manifest = create_manifest(output_directory);
+    println!("Provenance hash: {}", manifest.provenance_hash);
+    if provenance {
+        write_provenance(&manifest, output_directory);
+    }
+
+    let path_buffer = Path::new(output_directory).join(MANIFEST_FILE);
+    let mut file = File::create(&path_buffer)
+        .unwrap_or_else(|_| panic!("Could not create file at path {}", path_buffer.display()));
+    write!(
+        file,
+        "{}",
+        serde_json::to_string_pretty(&manifest).expect("Could not serialize manifest JSON")
+    )
+    .unwrap_or_else(|_| panic!("Could not write to file at path {}", path_buffer.display()));
+}
+
+/// Writes the provenance hash and the image hashes it is made from to `.resin/provenance.json`
+fn write_provenance(manifest: &Manifest, output_directory: &str) {
+    let resin_directory = Path::new(output_directory).join(".resin");
+    create_dir_all(&resin_directory).unwrap_or_else(|_| {
+        panic!(
+            "Could not create directory at {}",
+            resin_directory.display()
+        )
+    });
+    let record = Provenance {
+        provenance_hash: &manifest.provenance_hash,
+        image_hashes: manifest
+            .files
+            .iter()
+            .filter(|(file_name, _)| is_token_image(file_name))
+            .map(|(_, hash)| hash.as_str())
+            .collect(),
+    };
+    let path_buffer = resin_directory.join("provenance.json");
+    let mut file = File::create(&path_buffer)
+        .unwrap_or_else(|_| panic!("Could not create file at path {}", path_buffer.display()));
+    write!(
+        file,
+        "{}",
+        serde_json::to_string(&record).expect("Could not serialize provenance JSON")
+    )
+    .unwrap_or_else(|_| panic!("Could not write to file at path {}", path_buffer.display()));
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Provenance<'a> {
+    provenance_hash: &'a str,
+    image_hashes: Vec<&'a str>,
+}
+
+/// Differences between the files in `folder` and its manifest, empty if nothing changed
+///
+/// Output folders without a manifest of their own are checked folder by folder, as written
+/// for `"tierOutput": "folders"`.
+pub fn verify(folder: &str) -> Vec<String> {
+    if Path::new(folder).join(MANIFEST_FILE).exists() {
+        return verify_folder(folder);
+    }
+    let mut tiers: Vec<PathBuf> = read_dir(folder)
+        .unwrap_or_else(|_| panic!("Could not read directory {}", folder))
+        .flatten()
+        .map(|f| f.path())
+        .filter(|f| f.join(MANIFEST_FILE).is_file())
+        .collect();
+    if tiers.is_empty() {
+        return verify_folder(folder);
+    }
+    tiers.sort();
+    tiers
+        .iter()
+        .flat_map(|tier| {
+            let name = tier.file_name().unwrap_or_default().to_string_lossy();
+            verify_folder(&tier.to_string_lossy())
+                .into_iter()
+                .map(move |problem| format!("{}: {}", name, problem))
+        })
+        .collect()
+}
+
+fn verify_folder(folder: &str) -> Vec<String> {
+    let manifest_path = Path::new(folder).join(MANIFEST_FILE);
+    let manifest: Manifest = serde_json::from_str(
+        &read_to_string(&manifest_path)
+            .unwrap_or_else(|_| panic!("Could not read manifest at {}", manifest_path.display())),
+    )
+    .unwrap_or_else(|_| panic!("Could not parse manifest at {}", manifest_path.display()));
+
+    let current = create_manifest(folder);
+    let mut problems = vec![];
+    for (file_name, hash) in &manifest.files {
+        match current.files.get(file_name) {
+            Some(current_hash) if current_hash != hash => {
+                problems.push(format!("{} has been modified", file_name))
+            }
+            Some(_) => {}
+            None => problems.push(format!("{} is missing", file_name)),
+        }
+    }
+    for file_name in current.files.keys() {
+        if !manifest.files.contains_key(file_name) {
+            problems.push(format!("{} is not in the manifest", file_name));
+        }
+    }
+    if current.provenance_hash != manifest.provenance_hash {
+        problems.push(format!(
+            "Provenance hash is {}, manifest has {}",
+            current.provenance_hash, manifest.provenance_hash
+        ));
+    }
+    problems
+}
+
+fn create_manifest(folder: &str) -> Manifest {
+    let mut file_names: Vec<String> = read_dir(folder)
+        .unwrap_or_else(|_| panic!("Could not read directory {}", folder))
+        .flatten()
+        .filter(|f| f.path().is_file())
+        .map(|f| f.file_name().to_string_lossy().to_string())
+        .filter(|f| f != MANIFEST_FILE)
+        .collect();
+    // Tokens in ID order with metadata after its image, then any other files
+    file_names.sort_by_key(|f| {
+        let path = Path::new(f);
+        let id = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.parse::<u32>().ok());
+        let is_metadata = path.extension().is_some_and(|e| e == "json");
+        (id.is_none(), id, is_metadata, f.clone())
+    });
+
+    let mut files = IndexMap::new();
+    let mut image_hashes = vec![];
+    for file_name in file_names {
+        let path = Path::new(folder).join(&file_name);
+        let data = read(&path)
+            .unwrap_or_else(|_| panic!("Could not read file at path {}", path.display()));
+        let file_hash = hash(&data);
+        if is_token_image(&file_name) {
+            image_hashes.push(file_hash.clone());
+        }
+        files.insert(file_name, file_hash);
+    }
+
+    Manifest {
+        provenance_hash: provenance_hash(&image_hashes),
+        files,
+    }
+}
+
+/// Whether `file_name` is the image of a single token, e.g. `5.png`
+fn is_token_image(file_name: &str) -> bool {
+    let path = Path::new(file_name);
+    path.extension().is_some_and(|e| e != "json")
+        && path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .is_some_and(|s| s.parse::<u32>().is_ok())
+}
+
+/// Hex encoded SHA-256 of `data`
+pub fn hash(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Hash of every image hash concatenated in token ID order
+pub fn provenance_hash(image_hashes: &[String]) -> String {
+    hash(image_hashes.concat().as_bytes())
+}
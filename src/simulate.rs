@@ -0,0 +1,113 @@
+use indexmap::IndexMap;
+use rand::prelude::*;
+use std::collections::HashSet;
+
+use crate::{
+    config::{Attribute, Config},
+    metadata::{self, Token},
+};
+
+/// Stands in for a trait of a layer that was left out of a roll
+pub const NONE_TRAIT: &str = "(none)";
+
+/// Trait counts of many collections rolled in memory from the same config
+pub struct Simulation {
+    pub runs: u32,
+    /// Rolled tokens over every run, one of ones are not counted
+    pub rolled: u32,
+    pub layers: IndexMap<String, IndexMap<String, TraitStats>>,
+}
+
+/// How often a trait came up over every run
+#[derive(Default)]
+pub struct TraitStats {
+    share_sum: f64,
+    share_squared_sum: f64,
+    present: u32,
+}
+
+impl TraitStats {
+    /// Average share of rolled tokens with this trait
+    pub fn mean(&self, runs: u32) -> f64 {
+        self.share_sum / runs as f64
+    }
+
+    /// 95% confidence interval of the expected share, from the spread between runs
+    pub fn confidence_interval(&self, runs: u32) -> (f64, f64) {
+        let mean = self.mean(runs);
+        let variance = (self.share_squared_sum / runs as f64 - mean * mean).max(0.0);
+        let margin = 1.96 * (variance / runs as f64).sqrt();
+        ((mean - margin).max(0.0), (mean + margin).min(1.0))
+    }
+
+    /// Share of runs where at least one token had this trait
+    pub fn appears(&self, runs: u32) -> f64 {
+        self.present as f64 / runs as f64
+    }
+}
+
+/// Rolls the tokens of `config` `runs` times, the same way `resin generate` does
+pub fn simulate(config: &Config, runs: u32, rng: &mut StdRng) -> Simulation {
+    // Every trait a layer could roll, so traits that never come up are reported too
+    let mut layers: IndexMap<String, IndexMap<String, TraitStats>> = IndexMap::new();
+    for (layer_name, tables) in &config.attributes {
+        let traits = layers.entry(layer_name.clone()).or_default();
+        for (key, table) in tables {
+            match table {
+                Attribute::Keyed(files) => {
+                    for file in files.keys() {
+                        traits.entry(file.clone()).or_default();
+                    }
+                }
+                Attribute::Standard(_) => {
+                    traits.entry(key.clone()).or_default();
+                }
+            }
+        }
+        if config
+            .layers
+            .get(layer_name)
+            .is_some_and(|l| l.none_probability.is_some())
+        {
+            traits.entry(NONE_TRAIT.to_string()).or_default();
+        }
+    }
+
+    let mut rolled = 0;
+    for _ in 0..runs {
+        let mut counts: IndexMap<(&str, String), u32> = IndexMap::new();
+        let tokens = metadata::generate_tokens(config, &mut HashSet::new(), rng);
+        let mut run_rolled = 0;
+        for token in &tokens {
+            let attributes = match token {
+                Token::Rolled(attributes) => attributes,
+                Token::OneOfOne(_) => continue,
+            };
+            run_rolled += 1;
+            for layer_name in config.attributes.keys() {
+                let value = attributes
+                    .iter()
+                    .find(|t| &t.trait_type == layer_name)
+                    .and_then(|t| t.value.as_str())
+                    .unwrap_or(NONE_TRAIT);
+                *counts.entry((layer_name, value.to_string())).or_default() += 1;
+            }
+        }
+
+        for ((layer_name, value), count) in counts {
+            // Guaranteed rolls may use traits outside of every table
+            let stats = layers[layer_name].entry(value).or_default();
+            let share = count as f64 / run_rolled.max(1) as f64;
+            stats.share_sum += share;
+            stats.share_squared_sum += share * share;
+            stats.present += 1;
+        }
+        rolled += run_rolled;
+    }
+
+    Simulation {
+        runs,
+        rolled,
+        layers,
+    }
+}
@@ -0,0 +1,134 @@
+use resvg::{
+    tiny_skia::{Pixmap, Transform},
+    usvg::{fontdb::Database, Options, Tree, WriteOptions},
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+};
+
+use crate::config::Svg;
+
+pub fn is_svg(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("svg"))
+}
+
+/// Parsing options with the system fonts loaded once, text in layers is drawn with them
+fn options(path: &Path) -> Options<'static> {
+    static FONTS: OnceLock<Arc<Database>> = OnceLock::new();
+    let fontdb = FONTS.get_or_init(|| {
+        let mut fontdb = Database::new();
+        fontdb.load_system_fonts();
+        Arc::new(fontdb)
+    });
+    Options {
+        resources_dir: path.parent().map(Path::to_path_buf),
+        fontdb: fontdb.clone(),
+        ..Options::default()
+    }
+}
+
+fn read_tree(path: &Path) -> Tree {
+    let data = fs::read(path).unwrap_or_else(|_| panic!("Could not read layer {}", path.display()));
+    Tree::from_data(&data, &options(path))
+        .unwrap_or_else(|e| panic!("Could not parse SVG layer {}: {}", path.display(), e))
+}
+
+/// Size of the SVG layer at `path` when rasterized, or why it can't be parsed
+pub fn dimensions(path: &Path, settings: &Svg) -> Result<(u32, u32), String> {
+    let data = fs::read(path).map_err(|e| e.to_string())?;
+    let tree = Tree::from_data(&data, &options(path)).map_err(|e| e.to_string())?;
+    Ok(render_size(&tree, settings))
+}
+
+/// The configured size, or the SVG's own size, keeping its aspect ratio if only one of width
+/// and height is set
+fn render_size(tree: &Tree, settings: &Svg) -> (u32, u32) {
+    let size = tree.size();
+    match (settings.width, settings.height) {
+        (Some(width), Some(height)) => (width, height),
+        (Some(width), None) => (
+            width,
+            (width as f32 * size.height() / size.width()).round() as u32,
+        ),
+        (None, Some(height)) => (
+            (height as f32 * size.width() / size.height()).round() as u32,
+            height,
+        ),
+        (None, None) => (size.width().ceil() as u32, size.height().ceil() as u32),
+    }
+}
+
+/// Draws `tree` at its rendered size
+fn render(tree: &Tree, settings: &Svg, path: &Path) -> Pixmap {
+    let size = tree.size();
+    let (width, height) = render_size(tree, settings);
+    let mut pixmap = Pixmap::new(width, height)
+        .unwrap_or_else(|| panic!("Could not draw {} at {}x{}", path.display(), width, height));
+    let transform =
+        Transform::from_scale(width as f32 / size.width(), height as f32 / size.height());
+    resvg::render(tree, transform, &mut pixmap.as_mut());
+    pixmap
+}
+
+/// Rasterizes the SVG layer at `path` into a PNG at `output`
+pub fn rasterize(path: &Path, settings: &Svg, output: &Path) {
+    render(&read_tree(path), settings, path)
+        .save_png(output)
+        .unwrap_or_else(|e| panic!("Could not write {}: {}", output.display(), e));
+}
+
+/// Nests SVG `layers`, from bottom to top and at their `offsets`, into a single SVG document
+///
+/// Element IDs are prefixed per layer so gradients and clip paths of different layers don't
+/// clash, and text is converted to paths so the document doesn't depend on installed fonts.
+pub fn nest(layers: &[PathBuf], offsets: &[[u32; 2]]) -> Result<String, String> {
+    let mut size = None;
+    let mut nested = String::new();
+    for (i, (path, [x, y])) in layers.iter().zip(offsets).enumerate() {
+        let tree = read_tree(path);
+        size.get_or_insert(tree.size());
+        let layer = tree.to_string(&WriteOptions {
+            id_prefix: Some(format!("l{}-", i)),
+            ..WriteOptions::default()
+        });
+        // Each layer keeps its root <svg> element, positioned at its offset
+        let start = layer
+            .find("<svg")
+            .ok_or_else(|| format!("{} has no root <svg> element", path.display()))?;
+        nested.push_str(&format!("<svg x=\"{}\" y=\"{}\"", x, y));
+        nested.push_str(&layer[start + "<svg".len()..]);
+    }
+    let size = size.ok_or("Token has no layers")?;
+    Ok(format!(
+        concat!(
+            "<svg width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\" ",
+            "xmlns=\"http://www.w3.org/2000/svg\" ",
+            "xmlns:xlink=\"http://www.w3.org/1999/xlink\">\n{2}</svg>\n"
+        ),
+        size.width(),
+        size.height(),
+        nested
+    ))
+}
+
+/// Writes a token whose layers are all SVG, as `output` with an `svg` and/or `png` extension
+pub fn create_image(layers: &[PathBuf], offsets: &[[u32; 2]], settings: &Svg, output: &Path) {
+    let document = nest(layers, offsets)
+        .unwrap_or_else(|e| panic!("Could not nest SVG layers for {}: {}", output.display(), e));
+    if settings.output.svg() {
+        let path = output.with_extension("svg");
+        fs::write(&path, &document)
+            .unwrap_or_else(|_| panic!("Could not write {}", path.display()));
+    }
+    if settings.output.png() {
+        let path = output.with_extension("png");
+        let tree = Tree::from_str(&document, &options(&layers[0]))
+            .unwrap_or_else(|e| panic!("Could not parse nested SVG for {}: {}", path.display(), e));
+        render(&tree, settings, &path)
+            .save_png(&path)
+            .unwrap_or_else(|e| panic!("Could not write {}: {}", path.display(), e));
+    }
+}
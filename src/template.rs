@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+
+/// A metadata field with `{{variable}}` placeholders, e.g. `{{name}} #{{id:04}}`
+///
+/// A variable may end with `:N` to right-align the value to a width of `N`, or `:0N` to pad
+/// it with zeros instead of spaces.
+#[derive(Debug, PartialEq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Text(String),
+    Variable {
+        name: String,
+        width: usize,
+        zero_pad: bool,
+    },
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Template, String> {
+        let mut segments = vec![];
+        let mut rest = source;
+
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                segments.push(Segment::Text(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find("}}")
+                .ok_or_else(|| format!("Unclosed {{{{ in template \"{}\"", source))?;
+            let variable = rest[start + 2..start + end].trim();
+
+            let (name, format) = variable.split_once(':').unwrap_or((variable, ""));
+            if name.is_empty() {
+                return Err(format!("Empty variable in template \"{}\"", source));
+            }
+            let width = match format {
+                "" => 0,
+                f => f
+                    .parse::<usize>()
+                    .map_err(|_| format!("Invalid format \"{}\" in template \"{}\"", f, source))?,
+            };
+
+            segments.push(Segment::Variable {
+                name: name.trim().to_string(),
+                width,
+                zero_pad: format.starts_with('0'),
+            });
+            rest = &rest[start + end + 2..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest.to_string()));
+        }
+
+        Ok(Template { segments })
+    }
+
+    /// Names of every variable used in the template
+    pub fn variables(&self) -> impl Iterator<Item = &str> {
+        self.segments.iter().filter_map(|s| match s {
+            Segment::Variable { name, .. } => Some(name.as_str()),
+            Segment::Text(_) => None,
+        })
+    }
+
+    /// Fills in variables from `values`, missing variables render as empty
+    pub fn render(&self, values: &HashMap<String, String>) -> String {
+        let mut rendered = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => rendered.push_str(text),
+                Segment::Variable {
+                    name,
+                    width,
+                    zero_pad,
+                } => {
+                    let value = values.get(name).map(String::as_str).unwrap_or_default();
+                    if *zero_pad {
+                        rendered.push_str(&format!("{:0>width$}", value, width = width));
+                    } else {
+                        rendered.push_str(&format!("{:>width$}", value, width = width));
+                    }
+                }
+            }
+        }
+        rendered
+    }
+}
@@ -1,8 +1,13 @@
 #[cfg(test)]
 mod config {
     use crate::config::{self, Attribute};
-    use std::io::Write;
-    use tempfile::NamedTempFile;
+    use indexmap::IndexMap;
+    use serde_json::{json, Value};
+    use std::{
+        fs::{create_dir_all, write},
+        io::Write,
+    };
+    use tempfile::{tempdir, NamedTempFile};
 
     pub const SAMPLE_CONFIG: &str = r#"
     {
@@ -67,6 +72,18 @@ mod config {
     }
     "#;
 
+    /// A config of one token with only the required fields, and `fields` merged into it
+    pub fn minimal_config(fields: Value) -> Value {
+        let mut config = json!({
+            "name": "Test", "collectionName": "Test", "symbol": "TST",
+            "description": "", "externalUrl": "",
+            "guaranteedAttributeRolls": [],
+            "amount": 1
+        });
+        config::merge(&mut config, fields);
+        config
+    }
+
     #[test]
     fn parse() {
         let file = NamedTempFile::new().expect("Could not create temp config file");
@@ -88,10 +105,7 @@ mod config {
             assert_eq!(a.len(), 6);
             assert_eq!(a.get("blue.png").unwrap(), &0.04f32);
         } else {
-            assert!(
-                false,
-                "wasn't able to deserialize background_attribute key into keyed attribute"
-            )
+            panic!("wasn't able to deserialize background_attribute key into keyed attribute")
         }
 
         let face_attribute = parsed_config.attributes.get("face").unwrap();
@@ -99,19 +113,13 @@ mod config {
             assert_eq!(a.len(), 5);
             assert_eq!(a.get("cyan-face.png").unwrap(), &0.07f32);
         } else {
-            assert!(
-                false,
-                "wasn't able to deserialize face_attribute key into keyed attribute"
-            )
+            panic!("wasn't able to deserialize face_attribute key into keyed attribute")
         }
         if let Attribute::Keyed(a) = face_attribute.get("joker").unwrap() {
             assert_eq!(a.len(), 1);
             assert_eq!(a.get("gold-face.png").unwrap(), &0.11f32);
         } else {
-            assert!(
-                false,
-                "wasn't able to deserialize face_attribute key into keyed attribute"
-            )
+            panic!("wasn't able to deserialize face_attribute key into keyed attribute")
         }
 
         let mouth_attribute = parsed_config.attributes.get("mouth").unwrap();
@@ -119,25 +127,22 @@ mod config {
             assert_eq!(a.len(), 2);
             assert_eq!(a.get("block-mouth.png").unwrap(), &0.23f32);
         } else {
-            assert!(
-                false,
-                "wasn't able to deserialize mouth_attribute key into keyed attribute"
-            )
+            panic!("wasn't able to deserialize mouth_attribute key into keyed attribute")
         }
         if let Attribute::Keyed(a) = mouth_attribute.get("_key:joker").unwrap() {
             assert_eq!(a.len(), 1);
             assert_eq!(a.get("triangle-mouth.png").unwrap(), &0.68f32);
         } else {
-            assert!(
-                false,
-                "wasn't able to deserialize mouth_attribute key into keyed attribute"
-            )
+            panic!("wasn't able to deserialize mouth_attribute key into keyed attribute")
         }
 
         assert_eq!(parsed_config.guaranteed_attribute_rolls.len(), 1);
-        assert_eq!(parsed_config.guaranteed_attribute_rolls[0].len(), 4);
+        let traits = parsed_config.guaranteed_attribute_rolls[0]
+            .traits(&parsed_config.attributes)
+            .unwrap();
+        assert_eq!(traits.len(), 4);
         assert_eq!(
-            parsed_config.guaranteed_attribute_rolls[0],
+            traits.values().collect::<Vec<_>>(),
             vec![
                 "black.png",
                 "white-face.png",
@@ -145,15 +150,282 @@ mod config {
                 "smile-mouth.png"
             ]
         );
+        assert_eq!(
+            traits.keys().collect::<Vec<_>>(),
+            vec!["background", "face", "eyes", "mouth"]
+        );
         assert_eq!(parsed_config.amount, 1337);
     }
 
+    #[test]
+    fn merge() {
+        let mut base = json!({
+            "name": "Base",
+            "amount": 10,
+            "attributes": {
+                "background": { "_": { "blue.png": 1 } },
+                "face": { "_": { "cyan-face.png": 1, "teal-face.png": 1 } }
+            },
+            "templates": { "name": "{{name}}", "description": "Base" }
+        });
+        config::merge(
+            &mut base,
+            json!({
+                "amount": 2,
+                "attributes": { "face": { "_": { "gold-face.png": 1 } } },
+                "templates": { "name": "{{name}} Legendary" }
+            }),
+        );
+
+        assert_eq!(
+            base,
+            json!({
+                "name": "Base",
+                "amount": 2,
+                "attributes": {
+                    "background": { "_": { "blue.png": 1 } },
+                    "face": { "_": { "gold-face.png": 1 } }
+                },
+                "templates": { "name": "{{name}} Legendary", "description": "Base" }
+            })
+        );
+    }
+
     #[test]
     #[should_panic]
     fn invalid_path() {
         config::parse("/path/to/nowhere").unwrap();
     }
 
+    #[test]
+    fn extends_and_include() {
+        let dir = tempdir().unwrap();
+        create_dir_all(dir.path().join("base").join("layers")).unwrap();
+        write(dir.path().join("base").join("base.json"), SAMPLE_CONFIG).unwrap();
+        write(
+            dir.path().join("base").join("overrides.csv"),
+            "id,name\n0,Zero\n",
+        )
+        .unwrap();
+        write(
+            dir.path().join("base").join("overrides.json"),
+            r#"{ "extends": "base.json", "overrides": "overrides.csv" }"#,
+        )
+        .unwrap();
+        write(
+            dir.path().join("base").join("layers").join("eyes.yaml"),
+            "_:\n  egg-eyes.png: 1\n",
+        )
+        .unwrap();
+
+        let config_path = dir.path().join("config.json");
+        write(
+            &config_path,
+            r#"{
+                "extends": "base/overrides.json",
+                "include": ["base/layers"],
+                "name": "Child",
+                "attributes": { "background": { "_": { "blue.png": 1 } } }
+            }"#,
+        )
+        .unwrap();
+        let location = config_path.to_str().unwrap();
+
+        let config = config::parse(location).unwrap();
+        assert_eq!(config.name, "Child");
+        assert_eq!(config.collection_name, "Special NFTs");
+        assert_eq!(config.amount, 1337);
+        // Layers keep the order of the base config
+        let layers: Vec<&String> = config.attributes.keys().collect();
+        assert_eq!(layers, ["_key", "background", "face", "eyes", "mouth"]);
+        assert_eq!(
+            config.attributes["background"]["_"],
+            single("blue.png", 1.0)
+        );
+        assert_eq!(config.attributes["eyes"]["_"], single("egg-eyes.png", 1.0));
+        // Relative to the base config that set it
+        assert_eq!(config.token_overrides[&0].name.as_deref(), Some("Zero"));
+
+        let sets = [
+            "amount=500".to_string(),
+            "templates.name=Token {{id}}".to_string(),
+        ];
+        let config = config::parse_tiers(location, &sets)
+            .unwrap()
+            .remove(0)
+            .config;
+        assert_eq!(config.amount, 500);
+        assert_eq!(config.templates.name, "Token {{id}}");
+        assert_eq!(config.name, "Child");
+
+        assert!(config::parse_tiers(location, &["amount".to_string()]).is_err());
+
+        assert_eq!(
+            config::env_set("RESIN_AMOUNT", "500").as_deref(),
+            Some("amount=500")
+        );
+        assert_eq!(
+            config::env_set("RESIN_TEMPLATES__EXTERNAL_URL", "https://x.y/{{id}}").as_deref(),
+            Some("templates.externalUrl=https://x.y/{{id}}")
+        );
+        assert_eq!(config::env_set("PATH", "/bin"), None);
+    }
+
+    #[test]
+    fn include_folder_order() {
+        let dir = tempdir().unwrap();
+        let layers = dir.path().join("layers");
+        create_dir_all(&layers).unwrap();
+        write(layers.join("02_face.yaml"), "_:\n  cyan-face.png: 1\n").unwrap();
+        write(layers.join("10_mouth.yaml"), "_:\n  smile.png: 1\n").unwrap();
+        write(layers.join("1_background.yaml"), "_:\n  blue.png: 1\n").unwrap();
+        let mut config: serde_json::Value = serde_json::from_str(SAMPLE_CONFIG).unwrap();
+        config["include"] = json!(["layers"]);
+        config["attributes"] = json!({ "eyes": { "_": { "egg-eyes.png": 1 } } });
+        let config_path = dir.path().join("config.json");
+        write(&config_path, config.to_string()).unwrap();
+        let location = config_path.to_str().unwrap();
+
+        let config = config::parse(location).unwrap();
+        let layers: Vec<&String> = config.attributes.keys().collect();
+        assert_eq!(layers, ["background", "face", "mouth", "eyes"]);
+
+        // Alphabetical order would silently change the z-order
+        write(
+            dir.path().join("layers").join("eyes.yaml"),
+            "_:\n  egg-eyes.png: 1\n",
+        )
+        .unwrap();
+        match config::parse(location) {
+            Err(e) => assert!(e.to_string().contains("eyes.yaml needs a number"), "{}", e),
+            Ok(_) => panic!("Unnumbered layer files were included"),
+        }
+    }
+
+    fn single(file: &str, weight: f32) -> Attribute {
+        Attribute::Keyed(IndexMap::from([(file.to_string(), weight)]))
+    }
+
+    #[test]
+    fn extends_itself() {
+        let dir = tempdir().unwrap();
+        write(dir.path().join("a.json"), r#"{ "extends": "b.json" }"#).unwrap();
+        write(dir.path().join("b.json"), r#"{ "extends": "a.json" }"#).unwrap();
+
+        let error = config::parse(dir.path().join("a.json").to_str().unwrap())
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("extends itself"), "{}", error);
+    }
+
+    #[test]
+    fn numeric_attribute_validation() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        let mut config: serde_json::Value = serde_json::from_str(SAMPLE_CONFIG).unwrap();
+        config["layers"] = json!({ "eyes": { "displayName": "Eye Style" } });
+
+        for (numeric, message) in [
+            (
+                json!({ "face": { "value": 1 } }),
+                "same trait type as layer face",
+            ),
+            (
+                json!({ "Eye Style": { "value": 1 } }),
+                "same trait type as layer eyes",
+            ),
+            (
+                json!({ "Birthday": { "displayType": "date", "value": 1546360800.5 } }),
+                "Birthday is a date",
+            ),
+            (
+                json!({ "Birthday": {
+                    "displayType": "date", "value": 0, "from": { "face": { "_": 0.5 } }
+                } }),
+                "Birthday is a date",
+            ),
+        ] {
+            config["numericAttributes"] = numeric;
+            write(&path, config.to_string()).unwrap();
+            let error = config::parse(path.to_str().unwrap()).err().unwrap();
+            assert!(error.to_string().contains(message), "{}", error);
+        }
+    }
+
+    #[test]
+    fn weight_mode() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        let mut config: serde_json::Value = serde_json::from_str(SAMPLE_CONFIG).unwrap();
+        config["weightMode"] = json!("percent");
+        config["guaranteedAttributeRolls"] = json!([]);
+        config["attributes"] = json!({
+            "background": { "_": { "blue.png": 60, "green.png": 40 } },
+            "face": {
+                "joker": { "gold-face.png": 100 },
+                "_": { "cyan-face.png": 50 },
+                "teal-face.png": 40
+            }
+        });
+        write(&path, config.to_string()).unwrap();
+        let error = config::parse(path.to_str().unwrap()).err().unwrap();
+        assert!(
+            error.to_string().contains(
+                "Weights of table _ in layer face add up to 90, but weightMode requires 100"
+            ),
+            "{}",
+            error
+        );
+
+        config["attributes"]["face"]["teal-face.png"] = json!(50);
+        write(&path, config.to_string()).unwrap();
+        let parsed = config::parse(path.to_str().unwrap()).unwrap();
+        let tables = config::weight_tables(&parsed.attributes["face"]);
+        let names: Vec<_> = tables.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, ["joker", "_"]);
+        assert_eq!(tables[1].1.get("teal-face.png"), Some(&50.0));
+        let warnings = config::weight_warnings(&parsed);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("Layer face has plain weights"));
+
+        config["weightMode"] = json!("probability");
+        write(&path, config.to_string()).unwrap();
+        assert!(config::parse(path.to_str().unwrap()).is_err());
+
+        // Relative tables can add up to anything, but mixed scales are flagged
+        config["weightMode"] = json!("relative");
+        config["attributes"]["background"]["_"] = json!({ "blue.png": 0.6, "green.png": 0.4 });
+        write(&path, config.to_string()).unwrap();
+        let parsed = config::parse(path.to_str().unwrap()).unwrap();
+        let warnings = config::weight_warnings(&parsed);
+        assert!(
+            warnings.iter().any(|w| w.contains("set weightMode")),
+            "{:?}",
+            warnings
+        );
+
+        // Weights meant out of 1 that fall short, even a single one, still always roll
+        config["attributes"] = json!({
+            "_key": { "joker": 0.54 },
+            "background": { "_": { "blue.png": 0.6, "green.png": 0.4 } },
+            "face": { "joker": { "gold-face.png": 1 }, "_": { "cyan-face.png": 0.5, "teal-face.png": 0.3 } }
+        });
+        write(&path, config.to_string()).unwrap();
+        let parsed = config::parse(path.to_str().unwrap()).unwrap();
+        let warnings = config::weight_warnings(&parsed);
+        assert_eq!(
+            warnings,
+            [
+                "Weights of table _ in layer _key add up to 0.54, not 1, its only entry is rolled every time. Add a \"_\" entry for the rest or set weightMode",
+                "Weights of table _ in layer face add up to 0.8, not 1, they are only relative to each other. Set noneProbability to leave the layer out or set weightMode",
+            ]
+        );
+
+        config["attributes"]["background"]["_"] = json!({ "blue.png": -1, "green.png": 2 });
+        write(&path, config.to_string()).unwrap();
+        assert!(config::parse(path.to_str().unwrap()).is_err());
+    }
+
     #[test]
     #[should_panic]
     fn corrupted_file() {
@@ -167,174 +439,1481 @@ mod config {
 
 #[cfg(test)]
 mod metadata {
-    #[allow(unused_imports)]
-    use crate::metadata;
+    use super::config::SAMPLE_CONFIG;
+    use crate::{config, metadata};
+    use serde_json::{json, Value};
+    use std::{
+        collections::HashSet,
+        fs::{create_dir_all, read_to_string, write, File},
+        io::Write,
+        path::Path,
+    };
+    use tempfile::tempdir;
+
+    /// Writes `config` into `directory` and generates metadata into `directory/generated`
+    pub fn generate_from(config: &Value, directory: &Path) -> String {
+        let config_path = directory.join("config.json");
+        let mut file = File::create(&config_path).unwrap();
+        write!(file, "{}", config).unwrap();
+
+        let output = directory.join("generated");
+        metadata::generate(
+            config_path.to_str().unwrap(),
+            directory.to_str().unwrap(),
+            output.to_str().unwrap(),
+            None,
+            &[],
+        );
+        output.to_str().unwrap().to_string()
+    }
+
+    pub fn read_json(path: impl AsRef<Path>) -> Value {
+        serde_json::from_str(&read_to_string(path).unwrap()).unwrap()
+    }
 
     #[test]
     fn integration() {
-        assert!(true);
+        let dir = tempdir().unwrap();
+        let mut config: Value = serde_json::from_str(SAMPLE_CONFIG).unwrap();
+        config["amount"] = json!(20);
+        config["guaranteedAttributeRolls"] = json!([]);
+        let output = generate_from(&config, dir.path());
+
+        for id in 0..20 {
+            let public = read_json(Path::new(&output).join(format!("{}.json", id)));
+            assert_eq!(public["name"], format!("Very Special NFT #{}", id));
+            assert_eq!(public["image"], format!("{}.png", id));
+            assert_eq!(public["attributes"].as_array().unwrap().len(), 4);
+
+            let internal = read_json(
+                Path::new(&output)
+                    .join(".resin")
+                    .join(format!("{}.json", id)),
+            );
+            assert_eq!(internal["attributes"].as_array().unwrap().len(), 5);
+        }
+
+        let collection = read_json(Path::new(&output).join("collection.json"));
+        assert_eq!(collection["name"], "Special NFTs");
     }
 
     #[test]
-    fn attribute_generation() {
-        assert!(true);
+    fn optional_layers() {
+        let dir = tempdir().unwrap();
+        let mut config: Value = serde_json::from_str(SAMPLE_CONFIG).unwrap();
+        config["amount"] = json!(10);
+        config["guaranteedAttributeRolls"] = json!([]);
+        config["layers"] = json!({ "eyes": { "noneProbability": 1.0 } });
+        let output = generate_from(&config, dir.path());
+
+        let public = read_json(Path::new(&output).join("0.json"));
+        let attributes = public["attributes"].as_array().unwrap();
+        assert_eq!(attributes.len(), 3);
+        assert!(attributes.iter().all(|t| t["trait_type"] != "eyes"));
+
+        let dir = tempdir().unwrap();
+        config["noneValue"] = json!("None");
+        let output = generate_from(&config, dir.path());
+
+        let public = read_json(Path::new(&output).join("0.json"));
+        let attributes = public["attributes"].as_array().unwrap();
+        assert_eq!(attributes.len(), 4);
+        assert_eq!(
+            attributes[2],
+            json!({ "trait_type": "eyes", "value": "None" })
+        );
+
+        // Omitted layers are never handed to the compositor
+        let internal = read_json(Path::new(&output).join(".resin").join("0.json"));
+        let internal_attributes = internal["attributes"].as_array().unwrap();
+        assert!(internal_attributes
+            .iter()
+            .all(|t| t["trait_type"] != "eyes"));
     }
 
     #[test]
-    fn creation() {
-        assert!(true);
+    fn display_names() {
+        let dir = tempdir().unwrap();
+        let mut config: Value = serde_json::from_str(SAMPLE_CONFIG).unwrap();
+        config["amount"] = json!(1);
+        config["guaranteedAttributeRolls"] = json!([]);
+        config["nameStyle"] = json!("titleCase");
+        config["attributes"]["eyes"] = json!({ "_": { "heart-eyes.png": 1 } });
+        config["attributes"]["mouth"] = json!({ "_": { "smile_mouth.png": 1 } });
+        config["attributes"]["background"] = json!({ "_": { "blue.png": 1 } });
+        config["layers"] = json!({
+            "background": {
+                "displayName": "Backdrop",
+                "traits": { "blue.png": { "displayName": "Deep Sea" } }
+            }
+        });
+        let output = generate_from(&config, dir.path());
+
+        let public = read_json(Path::new(&output).join("0.json"));
+        let attributes = public["attributes"].as_array().unwrap();
+        assert_eq!(
+            attributes[0],
+            json!({ "trait_type": "Backdrop", "value": "Deep Sea" })
+        );
+        assert_eq!(
+            attributes[2],
+            json!({ "trait_type": "Eyes", "value": "Heart Eyes" })
+        );
+        assert_eq!(
+            attributes[3],
+            json!({ "trait_type": "Mouth", "value": "Smile Mouth" })
+        );
+
+        // Compositing still uses the original names
+        let internal = read_json(Path::new(&output).join(".resin").join("0.json"));
+        assert_eq!(
+            internal["attributes"][3],
+            json!({ "trait_type": "eyes", "value": "heart-eyes.png" })
+        );
     }
-}
 
-#[cfg(test)]
-mod art {
-    #[allow(unused_imports)]
-    use crate::art;
+    #[test]
+    fn numeric_attributes() {
+        let dir = tempdir().unwrap();
+        let mut config: Value = serde_json::from_str(SAMPLE_CONFIG).unwrap();
+        config["amount"] = json!(10);
+        config["guaranteedAttributeRolls"] = json!([]);
+        config["attributes"]["face"] = json!({ "_": { "gold-face.png": 1 } });
+        config["numericAttributes"] = json!({
+            "Generation": { "value": 2 },
+            "Level": { "displayType": "number", "min": 1, "max": 5, "maxValue": 5 },
+            "Luck": { "displayType": "boost_percentage", "min": 0.5, "max": 1.5 },
+            "Power": {
+                "displayType": "boost_number",
+                "value": 1,
+                "from": { "face": { "gold-face": 10, "_": 1 }, "eyes": { "egg-eyes.png": 3 } }
+            },
+            "Birthday": { "displayType": "date", "value": 1546360800 },
+            // Past the range of i64, so the sum becomes a float
+            "Supply": { "value": i64::MAX, "from": { "face": { "gold-face": 1 } } }
+        });
+        let output = generate_from(&config, dir.path());
+
+        for id in 0..10 {
+            let public = read_json(Path::new(&output).join(format!("{}.json", id)));
+            let attributes = public["attributes"].as_array().unwrap();
+            assert_eq!(attributes.len(), 10);
+            assert_eq!(
+                attributes[4],
+                json!({ "trait_type": "Generation", "value": 2 })
+            );
+
+            let level = &attributes[5];
+            assert_eq!(level["display_type"], "number");
+            assert_eq!(level["max_value"], 5);
+            assert!((1..=5).contains(&level["value"].as_i64().unwrap()));
+
+            let luck = attributes[6]["value"].as_f64().unwrap();
+            assert!((0.5..=1.5).contains(&luck));
+
+            let internal = read_json(
+                Path::new(&output)
+                    .join(".resin")
+                    .join(format!("{}.json", id)),
+            );
+            let egg_eyes = internal["attributes"][3]["value"] == "egg-eyes.png";
+            let power = &attributes[7];
+            assert_eq!(power["display_type"], "boost_number");
+            assert_eq!(power["value"], if egg_eyes { 14 } else { 11 });
+
+            assert_eq!(
+                attributes[8],
+                json!({ "display_type": "date", "trait_type": "Birthday", "value": 1546360800 })
+            );
+            assert_eq!(attributes[9]["value"], i64::MAX as f64 + 1.0);
+        }
+    }
 
     #[test]
-    fn read_metadata() {
-        assert!(true);
+    #[should_panic]
+    fn invalid_numeric_attribute() {
+        let dir = tempdir().unwrap();
+        let mut config: Value = serde_json::from_str(SAMPLE_CONFIG).unwrap();
+        config["numericAttributes"] = json!({ "Level": { "min": 1 } });
+        generate_from(&config, dir.path());
     }
 
     #[test]
-    fn creation() {
-        assert!(true);
+    fn templates() {
+        let dir = tempdir().unwrap();
+        let mut config: Value = serde_json::from_str(SAMPLE_CONFIG).unwrap();
+        config["amount"] = json!(3);
+        config["guaranteedAttributeRolls"] = json!([]);
+        config["nameStyle"] = json!("titleCase");
+        config["attributes"]["face"] = json!({ "_": { "gold-face.png": 1 } });
+        config["layers"] = json!({
+            "face": { "traits": { "gold-face.png": { "text": "shines brightly" } } }
+        });
+        config["templates"] = json!({
+            "name": "{{name}} the {{trait.face}} #{{id:04}}",
+            "description": "Edition {{edition}} of {{amount}}, {{text.face}}",
+            "externalUrl": "{{externalUrl}}/{{id}}"
+        });
+        config["overrides"] = json!("overrides.csv");
+        let mut overrides = File::create(dir.path().join("overrides.csv")).unwrap();
+        write!(
+            overrides,
+            "id,name,description,external_url\n2,The Chosen One,,https://example.com\n"
+        )
+        .unwrap();
+        let output = generate_from(&config, dir.path());
+
+        let public = read_json(Path::new(&output).join("1.json"));
+        assert_eq!(public["name"], "Very Special NFT the Gold Face #0001");
+        assert_eq!(public["description"], "Edition 2 of 3, shines brightly");
+        assert_eq!(public["external_url"], "https://veryspecial.nft/1");
+
+        let public = read_json(Path::new(&output).join("2.json"));
+        assert_eq!(public["name"], "The Chosen One");
+        assert_eq!(public["description"], "Edition 3 of 3, shines brightly");
+        assert_eq!(public["external_url"], "https://example.com");
     }
-}
 
-#[cfg(test)]
-mod init {
-    use crate::{
-        cmd::init,
-        config::{self, Attribute},
-        Init,
-    };
-    use std::fs::{create_dir_all, File};
-    use tempfile::tempdir;
+    #[test]
+    #[should_panic]
+    fn unknown_template_variable() {
+        let dir = tempdir().unwrap();
+        let mut config: Value = serde_json::from_str(SAMPLE_CONFIG).unwrap();
+        config["templates"] = json!({ "name": "{{trait.hat}}" });
+        generate_from(&config, dir.path());
+    }
 
     #[test]
-    fn from_scratch() {
-        let dir = tempdir().unwrap().path().join("assets");
-        let command_input = Init {
-            folder: dir.to_str().unwrap().to_string(),
-            overwrite: false,
-            from_existing: None,
+    fn guaranteed_rolls() {
+        let dir = tempdir().unwrap();
+        let mut config: Value = serde_json::from_str(SAMPLE_CONFIG).unwrap();
+        config["amount"] = json!(3);
+        config["guaranteedAttributeRolls"] = json!([
+            { "traits": { "face": "gold-face" }, "position": 2 },
+            { "background": "blue.png", "face": "teal-face.png", "eyes": "egg-eyes.png", "mouth": "block-mouth.png" },
+            ["red.png", "pink-face.png", "star-eyes.png", "smile-mouth.png"]
+        ]);
+        let output = generate_from(&config, dir.path());
+
+        let face = |id: u32| {
+            let internal = read_json(
+                Path::new(&output)
+                    .join(".resin")
+                    .join(format!("{}.json", id)),
+            );
+            internal["attributes"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|t| t["trait_type"] == "face")
+                .unwrap()["value"]
+                .clone()
         };
-        init::handle(command_input);
+        // Every guaranteed roll is included even though amount leaves no room for random rolls
+        assert_eq!(face(2), "gold-face.png");
+        let mut spread = vec![face(0), face(1)];
+        spread.sort_by_key(|f| f.to_string());
+        assert_eq!(spread, vec!["pink-face.png", "teal-face.png"]);
+    }
 
-        let parsed_config = config::parse(dir.join("config.json").to_str().unwrap()).unwrap();
+    #[test]
+    fn guaranteed_roll_uniqueness() {
+        let dir = tempdir().unwrap();
+        let mut config: Value = serde_json::from_str(SAMPLE_CONFIG).unwrap();
+        config["amount"] = json!(2);
+        config["requireUnique"] = json!(true);
+        config["attributes"] = json!({
+            "background": { "_": { "blue.png": 1, "red.png": 1 } },
+            "face": { "_": { "gold-face.png": 1 } }
+        });
+        config["guaranteedAttributeRolls"] =
+            json!([{ "traits": { "background": "blue.png" }, "position": "random" }]);
+        let output = generate_from(&config, dir.path());
 
-        assert_eq!(parsed_config.name, "NFT Title");
-        assert_eq!(parsed_config.collection_name, "NFT Collection");
-        assert_eq!(parsed_config.symbol, "SNFT");
-        assert_eq!(parsed_config.description, "Hello, NFT!");
-        assert_eq!(parsed_config.external_url, "https://example.com");
-        assert_eq!(parsed_config.attributes.len(), 3);
+        let mut backgrounds: Vec<String> = (0..2)
+            .map(|id| {
+                let internal = read_json(
+                    Path::new(&output)
+                        .join(".resin")
+                        .join(format!("{}.json", id)),
+                );
+                internal["attributes"][0]["value"]
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        backgrounds.sort();
+        assert_eq!(backgrounds, vec!["blue.png", "red.png"]);
+    }
 
-        let layer_1 = parsed_config.attributes.get("LAYER_NAME").unwrap();
-        if let Attribute::Keyed(a) = layer_1.get("_").unwrap() {
-            assert_eq!(a.len(), 1);
-            assert_eq!(a.get("FILE_NAME.png").unwrap(), &0.01f32);
-        } else {
-            assert!(
-                false,
-                "wasn't able to deserialize LAYER_NAME key into keyed attribute"
-            )
-        }
+    #[test]
+    #[should_panic]
+    fn too_many_guaranteed_rolls() {
+        let dir = tempdir().unwrap();
+        let mut config: Value = serde_json::from_str(SAMPLE_CONFIG).unwrap();
+        config["amount"] = json!(1);
+        config["guaranteedAttributeRolls"] =
+            json!([{ "face": "gold-face.png" }, { "face": "teal-face.png" }]);
+        generate_from(&config, dir.path());
+    }
 
-        let layer_2 = parsed_config.attributes.get("LAYER_NAME_2").unwrap();
-        if let Attribute::Keyed(a) = layer_2.get("_").unwrap() {
-            assert_eq!(a.len(), 1);
-            assert_eq!(a.get("FILE_NAME_2.png").unwrap(), &0.01f32);
-        } else {
-            assert!(
-                false,
-                "wasn't able to deserialize LAYER_NAME_2 key into keyed attribute"
-            )
-        }
-        if let Attribute::Keyed(a) = layer_2.get("KEY").unwrap() {
-            assert_eq!(a.len(), 1);
-            assert_eq!(a.get("FILE_NAME_3.png").unwrap(), &0.01f32);
-        } else {
-            assert!(
-                false,
-                "wasn't able to deserialize LAYER_NAME_2 key into keyed attribute"
-            )
+    #[test]
+    fn seeded() {
+        let mut config: Value = serde_json::from_str(SAMPLE_CONFIG).unwrap();
+        config["amount"] = json!(20);
+        config["guaranteedAttributeRolls"] = json!([]);
+        config["seed"] = json!(1337);
+
+        let first = tempdir().unwrap();
+        let first_output = generate_from(&config, first.path());
+        let second = tempdir().unwrap();
+        let second_output = generate_from(&config, second.path());
+
+        for id in 0..20 {
+            let file = format!("{}.json", id);
+            assert_eq!(
+                read_json(Path::new(&first_output).join(&file)),
+                read_json(Path::new(&second_output).join(&file))
+            );
         }
+    }
 
-        assert_eq!(parsed_config.guaranteed_attribute_rolls.len(), 1);
-        assert_eq!(parsed_config.guaranteed_attribute_rolls[0].len(), 2);
-        assert_eq!(
-            parsed_config.guaranteed_attribute_rolls[0],
-            vec!["FILE_NAME.png", "FILE_NAME_2.png"]
-        );
-        assert_eq!(parsed_config.amount, 10);
+    #[test]
+    fn shuffle() {
+        let dir = tempdir().unwrap();
+        let one_of_ones = dir.path().join("one-of-ones");
+        create_dir_all(&one_of_ones).unwrap();
+        write(one_of_ones.join("founder.png"), "founder").unwrap();
+
+        let mut config: Value = serde_json::from_str(SAMPLE_CONFIG).unwrap();
+        config["amount"] = json!(50);
+        config["seed"] = json!(7);
+        config["shuffle"] = json!(true);
+        config["oneOfOnes"] = json!("one-of-ones");
+        config["guaranteedAttributeRolls"] = json!([
+            { "traits": { "face": "gold-face.png" }, "position": 0 },
+            { "traits": { "face": "teal-face.png" } }
+        ]);
+        let output = generate_from(&config, dir.path());
+
+        let record = read_json(Path::new(&output).join(".resin").join("permutation.json"));
+        assert_eq!(record["seed"], 7);
+        let mut permutation: Vec<u64> = record["permutation"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|id| id.as_u64().unwrap())
+            .collect();
+        let moved = permutation
+            .iter()
+            .enumerate()
+            .filter(|(i, id)| *i as u64 != **id);
+        assert!(moved.count() > 0);
+        let unshuffled = permutation.clone();
+        permutation.sort();
+        assert_eq!(permutation, (0..50).collect::<Vec<u64>>());
+
+        // Tokens pinned to an ID and one of ones keep their place
+        assert_eq!(unshuffled[0], 0);
+        let internal = read_json(Path::new(&output).join(".resin").join("0.json"));
+        assert_eq!(internal["attributes"][2]["value"], "gold-face.png");
+        assert_eq!(internal["name"], "Very Special NFT #0");
+        let founder = (0..50)
+            .find(|id| Path::new(&output).join(format!("{}.png", id)).exists())
+            .unwrap();
+        assert_eq!(unshuffled[founder as usize], founder);
     }
 
     #[test]
-    fn from_existing() {
+    fn one_of_ones() {
         let dir = tempdir().unwrap();
-        let dir_path = dir.path();
+        let one_of_ones = dir.path().join("one-of-ones");
+        create_dir_all(&one_of_ones).unwrap();
+        write(one_of_ones.join("founder.png"), "founder").unwrap();
+        write(
+            one_of_ones.join("founder.json"),
+            json!({
+                "name": "{{name}} Founder",
+                "attributes": [{ "trait_type": "Type", "value": "Founder" }],
+                "position": 0
+            })
+            .to_string(),
+        )
+        .unwrap();
+        write(one_of_ones.join("wave.gif"), "wave").unwrap();
 
-        let dirs_to_create = vec!["attribute 1", "attribute 2", "attribute 3"];
-        for dir in &dirs_to_create {
-            create_dir_all(dir_path.join(dir)).unwrap();
-        }
+        let mut config: Value = serde_json::from_str(SAMPLE_CONFIG).unwrap();
+        config["amount"] = json!(5);
+        config["guaranteedAttributeRolls"] = json!([]);
+        config["oneOfOnes"] = json!("one-of-ones");
+        let output = generate_from(&config, dir.path());
+        let output = Path::new(&output);
 
-        let files_to_create = vec!["file 1.png", "file 2.png", "file 3.png"];
-        for file in files_to_create {
-            for dir in &dirs_to_create {
-                File::create(dir_path.join(dir).join(file)).unwrap();
-            }
-        }
+        let founder = read_json(output.join("0.json"));
+        assert_eq!(founder["name"], "Very Special NFT Founder");
+        assert_eq!(
+            founder["attributes"],
+            json!([{ "trait_type": "Type", "value": "Founder" }])
+        );
+        assert_eq!(read_to_string(output.join("0.png")).unwrap(), "founder");
+        assert!(!output.join(".resin").join("0.json").exists());
 
-        let command_input = Init {
-            folder: "".to_string(),
-            overwrite: false,
-            from_existing: dir_path.to_str().map(str::to_string),
-        };
-        init::handle(command_input);
+        let wave_id = (1..5)
+            .find(|id| output.join(format!("{}.gif", id)).exists())
+            .expect("Second one of one was not added");
+        let wave = read_json(output.join(format!("{}.json", wave_id)));
+        assert_eq!(wave["name"], format!("Very Special NFT #{}", wave_id));
+        assert_eq!(wave["properties"]["files"][0]["type"], "image/gif");
 
-        let parsed_config = config::parse(dir_path.join("config.json").to_str().unwrap()).unwrap();
+        // The rest of the collection is rolled as usual
+        let rolled = (1..5)
+            .filter(|id| output.join(".resin").join(format!("{}.json", id)).exists())
+            .count();
+        assert_eq!(rolled, 3);
 
-        assert_eq!(parsed_config.name, "NFT Title");
-        assert_eq!(parsed_config.collection_name, "NFT Collection");
-        assert_eq!(parsed_config.symbol, "SNFT");
-        assert_eq!(parsed_config.description, "Hello, NFT!");
-        assert_eq!(parsed_config.external_url, "https://example.com");
-        assert_eq!(parsed_config.attributes.len(), 3);
+        // One of ones are listed in the collection and counted with the rolled traits
+        let collection = read_json(output.join("collection.json"));
+        assert_eq!(collection["one_of_ones"], json!([0, wave_id]));
+        assert_eq!(collection["rarity"]["Type"], json!({ "Founder": 1 }));
+        let backgrounds: u64 = collection["rarity"]["background"]
+            .as_object()
+            .unwrap()
+            .values()
+            .map(|count| count.as_u64().unwrap())
+            .sum();
+        assert_eq!(backgrounds, 3);
 
-        let layer_1 = parsed_config.attributes.get("attribute 1").unwrap();
-        if let Attribute::Keyed(a) = layer_1.get("_").unwrap() {
-            assert_eq!(a.len(), 3);
-            assert_eq!(a.get("file 1.png").unwrap(), &0.1f32);
-        } else {
-            assert!(
-                false,
-                "wasn't able to deserialize attribute 1 key into keyed attribute"
-            )
-        }
+        // Sidecars that can't be read are reported as config errors
+        create_dir_all(one_of_ones.join("broken.json")).unwrap();
+        write(one_of_ones.join("broken.png"), "broken").unwrap();
+        let config_path = dir.path().join("config.json");
+        let error = config::parse_tiers(config_path.to_str().unwrap(), &[])
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("Could not read one of one"));
+    }
 
-        let layer_2 = parsed_config.attributes.get("attribute 2").unwrap();
-        if let Attribute::Keyed(a) = layer_2.get("_").unwrap() {
-            assert_eq!(a.len(), 3);
-            assert_eq!(a.get("file 2.png").unwrap(), &0.1f32);
-        } else {
-            assert!(
-                false,
-                "wasn't able to deserialize attribute 2 key into keyed attribute"
-            )
+    #[test]
+    fn tiers() {
+        let mut config: Value = serde_json::from_str(SAMPLE_CONFIG).unwrap();
+        config["guaranteedAttributeRolls"] = json!([]);
+        config["templates"] = json!({ "name": "{{tier}} #{{id}}" });
+        config["tiers"] = json!({
+            "common": { "amount": 5 },
+            "legendary": {
+                "amount": 2,
+                "attributes": { "face": { "_": { "gold-face.png": 1 } } }
+            }
+        });
+
+        let dir = tempdir().unwrap();
+        let output = generate_from(&config, dir.path());
+        for id in 0..7 {
+            let internal = read_json(
+                Path::new(&output)
+                    .join(".resin")
+                    .join(format!("{}.json", id)),
+            );
+            let tier = if id < 5 { "common" } else { "legendary" };
+            assert_eq!(internal["tier"], tier);
+            assert_eq!(internal["name"], format!("{} #{}", tier, id));
+            // Other layers are kept from the base config
+            assert_eq!(internal["attributes"].as_array().unwrap().len(), 5);
+            if tier == "legendary" {
+                assert_eq!(internal["attributes"][2]["value"], "gold-face.png");
+            }
+
+            let public = read_json(Path::new(&output).join(format!("{}.json", id)));
+            assert!(public.get("tier").is_none());
         }
+        assert!(!Path::new(&output).join("7.json").exists());
 
-        assert_eq!(parsed_config.guaranteed_attribute_rolls.len(), 0);
-        assert_eq!(parsed_config.amount, 10);
+        config["tierOutput"] = json!("folders");
+        let dir = tempdir().unwrap();
+        let output = generate_from(&config, dir.path());
+        let legendary = Path::new(&output).join("legendary");
+        assert!(legendary.join("1.json").exists());
+        assert!(!legendary.join("2.json").exists());
+        assert!(legendary.join("collection.json").exists());
+        assert!(Path::new(&output).join("common").join("4.json").exists());
     }
 
     #[test]
-    #[should_panic]
-    fn directory_already_exists() {
+    fn tiers_share_a_collection() {
         let dir = tempdir().unwrap();
-        let command_input = Init {
+        let one_of_ones = dir.path().join("one-of-ones");
+        create_dir_all(&one_of_ones).unwrap();
+        write(one_of_ones.join("founder.png"), "founder").unwrap();
+
+        let mut config: Value = serde_json::from_str(SAMPLE_CONFIG).unwrap();
+        config["attributes"] = json!({
+            "background": { "_": { "a.png": 1, "b.png": 1, "c.png": 1, "d.png": 1 } },
+            "face": { "_": { "cyan-face.png": 1 } }
+        });
+        config["requireUnique"] = json!(true);
+        config["seed"] = json!(1);
+        config["oneOfOnes"] = json!("one-of-ones");
+        config["guaranteedAttributeRolls"] = json!([{ "face": "founder-face.png" }]);
+        config["tiers"] = json!({ "common": { "amount": 3 }, "rare": { "amount": 3 } });
+        let output = generate_from(&config, dir.path());
+        let output = Path::new(&output);
+
+        // Guaranteed rolls and one of ones are made once, in the first tier
+        let founders = (0..6)
+            .filter(|id| output.join(format!("{}.png", id)).exists())
+            .collect::<Vec<_>>();
+        assert_eq!(founders.len(), 1);
+        assert!(founders[0] < 3);
+        let mut rolls = vec![];
+        for id in (0..6).filter(|id| *id != founders[0]) {
+            let internal = read_json(output.join(".resin").join(format!("{}.json", id)));
+            rolls.push(internal["attributes"].clone());
+        }
+        let guaranteed = rolls
+            .iter()
+            .filter(|r| r[1]["value"] == "founder-face.png")
+            .count();
+        assert_eq!(guaranteed, 1);
+
+        // Uniqueness holds across tiers, 4 backgrounds for 4 random rolls
+        let backgrounds: HashSet<_> = rolls
+            .iter()
+            .filter(|r| r[1]["value"] == "cyan-face.png")
+            .map(|r| r[0]["value"].to_string())
+            .collect();
+        assert_eq!(backgrounds.len(), 4);
+
+        config["tiers"]["rare"]["guaranteedAttributeRolls"] = json!([]);
+        write(dir.path().join("config.json"), config.to_string()).unwrap();
+        let error = config::parse_tiers(dir.path().join("config.json").to_str().unwrap(), &[])
+            .err()
+            .unwrap();
+        assert!(error
+            .to_string()
+            .contains("Tier rare can't set guaranteedAttributeRolls"));
+    }
+
+    #[test]
+    fn metadata_schema() {
+        let schema = serde_json::to_value(schemars::schema_for!(metadata::NFTMetadata)).unwrap();
+        let properties = schema["properties"].as_object().unwrap();
+        // Only kept in .resin
+        assert!(!properties.contains_key("tier"));
+        let trait_properties = schema["definitions"]["Trait"]["properties"]
+            .as_object()
+            .unwrap();
+
+        let dir = tempdir().unwrap();
+        let config: Value = serde_json::from_str(SAMPLE_CONFIG).unwrap();
+        let output = generate_from(&config, dir.path());
+        let token = read_json(Path::new(&output).join("0.json"));
+        for key in token.as_object().unwrap().keys() {
+            assert!(properties.contains_key(key), "{} is not in the schema", key);
+        }
+        for required in schema["required"].as_array().unwrap() {
+            assert!(token.get(required.as_str().unwrap()).is_some());
+        }
+        for attribute in token["attributes"].as_array().unwrap() {
+            for key in attribute.as_object().unwrap().keys() {
+                assert!(trait_properties.contains_key(key));
+            }
+        }
+        assert!(schema["definitions"]["PropertyFile"]["properties"]
+            .get("type")
+            .is_some());
+    }
+
+    #[test]
+    #[should_panic]
+    fn invalid_none_probability() {
+        let dir = tempdir().unwrap();
+        let mut config: Value = serde_json::from_str(SAMPLE_CONFIG).unwrap();
+        config["layers"] = json!({ "eyes": { "noneProbability": 1.5 } });
+        generate_from(&config, dir.path());
+    }
+}
+
+#[cfg(test)]
+mod template {
+    use crate::template::Template;
+    use std::collections::HashMap;
+
+    #[test]
+    fn render() {
+        let template = Template::parse("{{name}} #{{ id:04 }} ({{id:3}}) {{missing}}!").unwrap();
+        assert_eq!(
+            template.variables().collect::<Vec<_>>(),
+            vec!["name", "id", "id", "missing"]
+        );
+
+        let values = HashMap::from([
+            ("name".to_string(), "Resin".to_string()),
+            ("id".to_string(), "7".to_string()),
+        ]);
+        assert_eq!(template.render(&values), "Resin #0007 (  7) !");
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(Template::parse("{{name").is_err());
+        assert!(Template::parse("{{}}").is_err());
+        assert!(Template::parse("{{id:x}}").is_err());
+        assert_eq!(Template::parse("plain").unwrap().variables().count(), 0);
+    }
+}
+
+#[cfg(test)]
+mod provenance {
+    use crate::provenance::{self, Manifest};
+    use serde_json::{json, Value};
+    use std::fs::{create_dir_all, read_to_string, remove_file, write};
+    use tempfile::tempdir;
+
+    #[test]
+    fn manifest() {
+        let dir = tempdir().unwrap();
+        let folder = dir.path().to_str().unwrap();
+        write(dir.path().join("10.png"), "tenth").unwrap();
+        write(dir.path().join("10.json"), "{}").unwrap();
+        write(dir.path().join("2.png"), "second").unwrap();
+        write(dir.path().join("2.json"), "{}").unwrap();
+        write(dir.path().join("collection.json"), "{}").unwrap();
+
+        provenance::generate(folder, false);
+
+        let manifest: Manifest =
+            serde_json::from_str(&read_to_string(dir.path().join("manifest.json")).unwrap())
+                .unwrap();
+        assert_eq!(
+            manifest.files.keys().collect::<Vec<_>>(),
+            vec!["2.png", "2.json", "10.png", "10.json", "collection.json"]
+        );
+        let second = provenance::hash(b"second");
+        assert_eq!(
+            second,
+            "16367aacb67a4a017c8da8ab95682ccb390863780f7114dda0a0e0c55644c7c4"
+        );
+        assert_eq!(manifest.files["2.png"], second);
+        let tenth = provenance::hash(b"tenth");
+        assert_eq!(
+            manifest.provenance_hash,
+            provenance::hash(format!("{}{}", second, tenth).as_bytes())
+        );
+
+        assert!(provenance::verify(folder).is_empty());
+    }
+
+    #[test]
+    fn provenance_record() {
+        let dir = tempdir().unwrap();
+        let folder = dir.path().to_str().unwrap();
+        write(dir.path().join("0.png"), "first").unwrap();
+        write(dir.path().join("0.json"), "{}").unwrap();
+        write(dir.path().join("1.png"), "second").unwrap();
+
+        provenance::generate(folder, false);
+        assert!(!dir.path().join(".resin").join("provenance.json").exists());
+
+        provenance::generate(folder, true);
+        let record: Value = serde_json::from_str(
+            &read_to_string(dir.path().join(".resin").join("provenance.json")).unwrap(),
+        )
+        .unwrap();
+        let first = provenance::hash(b"first");
+        let second = provenance::hash(b"second");
+        assert_eq!(record["imageHashes"], json!([first, second]));
+        assert_eq!(
+            record["provenanceHash"],
+            provenance::hash(format!("{}{}", first, second).as_bytes())
+        );
+        // The record is kept out of the manifest and doesn't break verification
+        assert!(provenance::verify(folder).is_empty());
+    }
+
+    #[test]
+    fn tampering() {
+        let dir = tempdir().unwrap();
+        let folder = dir.path().to_str().unwrap();
+        write(dir.path().join("0.png"), "first").unwrap();
+        write(dir.path().join("0.json"), "{}").unwrap();
+        write(dir.path().join("1.png"), "second").unwrap();
+        provenance::generate(folder, false);
+
+        write(dir.path().join("0.png"), "re-rendered").unwrap();
+        remove_file(dir.path().join("1.png")).unwrap();
+        write(dir.path().join("2.json"), "{}").unwrap();
+
+        let problems = provenance::verify(folder);
+        assert_eq!(
+            problems,
+            vec![
+                "0.png has been modified".to_string(),
+                "1.png is missing".to_string(),
+                "2.json is not in the manifest".to_string(),
+                format!(
+                    "Provenance hash is {}, manifest has {}",
+                    provenance::provenance_hash(&[provenance::hash(b"re-rendered")]),
+                    serde_json::from_str::<Value>(
+                        &read_to_string(dir.path().join("manifest.json")).unwrap()
+                    )
+                    .unwrap()["provenanceHash"]
+                        .as_str()
+                        .unwrap()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn tier_folders() {
+        let dir = tempdir().unwrap();
+        for tier in ["common", "gold"] {
+            let folder = dir.path().join(tier);
+            create_dir_all(&folder).unwrap();
+            write(folder.join("0.png"), tier).unwrap();
+            write(folder.join("0.json"), "{}").unwrap();
+            provenance::generate(folder.to_str().unwrap(), false);
+        }
+        let root = dir.path().to_str().unwrap();
+        assert!(provenance::verify(root).is_empty());
+
+        write(dir.path().join("gold").join("0.png"), "re-rendered").unwrap();
+        let problems = provenance::verify(root);
+        assert_eq!(problems.len(), 2);
+        assert_eq!(problems[0], "gold: 0.png has been modified");
+    }
+}
+
+#[cfg(test)]
+mod art {
+    use super::{
+        config::{minimal_config, SAMPLE_CONFIG},
+        metadata::read_json,
+    };
+    use crate::{
+        animation, art, cmd::check, config::Config, metadata, metadata::NFTMetadata, output, Check,
+    };
+    use image::{
+        codecs::{
+            gif::{GifDecoder, GifEncoder},
+            webp::WebPDecoder,
+        },
+        AnimationDecoder, Delay, Frame, Rgb, RgbImage, Rgba, RgbaImage,
+    };
+    use serde_json::{json, Value};
+    use std::{
+        collections::HashMap,
+        fs::{create_dir_all, read_to_string, write, File},
+        io::BufReader,
+        panic,
+        path::{Path, PathBuf},
+    };
+    use tempfile::tempdir;
+
+    const SAMPLE_METADATA: &str = r#"
+    {
+        "name": "Very Special NFT #0",
+        "symbol": "SNFT",
+        "description": "",
+        "image": "0.png",
+        "external_url": "",
+        "attributes": [
+            { "trait_type": "_key", "value": "joker" },
+            { "trait_type": "background", "value": "blue.png" },
+            { "trait_type": "face", "value": "gold-face.png" },
+            { "trait_type": "eyes", "value": "star-eyes.png" },
+            { "trait_type": "mouth", "value": "triangle-mouth.png" }
+        ],
+        "properties": { "files": [], "category": "image" }
+    }
+    "#;
+
+    #[test]
+    fn layer_order() {
+        let mut config: Value = serde_json::from_str(SAMPLE_CONFIG).unwrap();
+        config["layers"] = json!({
+            "face": {
+                "traits": {
+                    "gold-face.png": {
+                        "parts": [
+                            { "file": "gold-face-back.png", "z": 1.5 },
+                            { "file": "gold-face-front.png", "z": 10 },
+                            { "file": "gold-face-middle.png" }
+                        ]
+                    }
+                }
+            }
+        });
+        let config: Config = serde_json::from_value(config).unwrap();
+        let metadata: NFTMetadata = serde_json::from_str(SAMPLE_METADATA).unwrap();
+
+        let paths = art::layer_paths(&metadata.attributes, &config, "assets");
+        let expected: Vec<_> = [
+            "background/blue.png",
+            "face/gold-face-back.png",
+            "face/gold-face-middle.png",
+            "eyes/star-eyes.png",
+            "mouth/triangle-mouth.png",
+            "face/gold-face-front.png",
+        ]
+        .iter()
+        .map(|p| Path::new("assets").join(p))
+        .collect();
+        assert_eq!(paths, expected);
+    }
+
+    /// Writes `config` into `directory` and generates the metadata and art of the layers in
+    /// `assets` into `directory/generated`
+    fn generate_art(config: &Value, directory: &Path, assets: &Path) -> PathBuf {
+        let config_path = directory.join("config.json");
+        write(&config_path, config.to_string()).unwrap();
+        let output = directory.join("generated");
+        let (config_path, assets) = (config_path.to_str().unwrap(), assets.to_str().unwrap());
+        let output_path = output.to_str().unwrap();
+        metadata::generate(config_path, assets, output_path, None, &[]);
+        art::generate(
+            config_path,
+            &[],
+            assets.to_string(),
+            output_path.to_string(),
+        );
+        output
+    }
+
+    fn write_animation(path: &Path, frames: &[[u8; 4]], delay: u32) {
+        let file = File::create(path).unwrap();
+        let mut encoder = GifEncoder::new(file);
+        encoder
+            .encode_frames(frames.iter().map(|color| {
+                Frame::from_parts(
+                    RgbaImage::from_pixel(4, 4, Rgba(*color)),
+                    0,
+                    0,
+                    Delay::from_numer_denom_ms(delay, 1),
+                )
+            }))
+            .unwrap();
+    }
+
+    #[test]
+    fn animated_layers() {
+        let dir = tempdir().unwrap();
+        let assets = dir.path().join("assets");
+        for layer in ["background", "face", "eyes/blink"] {
+            create_dir_all(assets.join(layer)).unwrap();
+        }
+        RgbaImage::from_pixel(4, 4, Rgba([0, 0, 255, 255]))
+            .save(assets.join("background").join("blue.png"))
+            .unwrap();
+        write_animation(
+            &assets.join("face").join("spin.gif"),
+            &[[255, 0, 0, 255], [0, 255, 0, 255]],
+            50,
+        );
+        for i in 0..3 {
+            RgbaImage::from_pixel(4, 4, Rgba([0, 0, 0, if i == 1 { 255 } else { 0 }]))
+                .save(assets.join("eyes").join("blink").join(format!("{}.png", i)))
+                .unwrap();
+        }
+        assert!(animation::is_animated(
+            &assets.join("face").join("spin.gif")
+        ));
+        assert!(animation::is_animated(&assets.join("eyes").join("blink")));
+        assert!(!animation::is_animated(
+            &assets.join("background").join("blue.png")
+        ));
+
+        let mut config = minimal_config(json!({
+            "attributes": {
+                "background": { "_": { "blue.png": 1 } },
+                "face": { "_": { "spin.gif": 1 } },
+                "eyes": { "_": { "blink": 1 } }
+            }
+        }));
+        let generate = |config: &Value| generate_art(config, dir.path(), &assets);
+
+        let output = generate(&config);
+        let metadata = read_json(output.join("0.json"));
+        assert_eq!(metadata["image"], "0.gif");
+        assert_eq!(metadata["animation_url"], "0.gif");
+        assert_eq!(
+            metadata["properties"],
+            json!({ "files": [{ "uri": "0.gif", "type": "image/gif" }], "category": "image" })
+        );
+        let decoder = GifDecoder::new(BufReader::new(File::open(output.join("0.gif")).unwrap()));
+        let frames = decoder.unwrap().into_frames().collect_frames().unwrap();
+        // As long as the blinking eyes, the spinning face loops and every frame keeps its delay
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].delay().numer_denom_ms(), (50, 1));
+        assert_eq!(frames[0].buffer().get_pixel(0, 0), &Rgba([255, 0, 0, 255]));
+        assert_eq!(frames[1].buffer().get_pixel(0, 0), &Rgba([0, 0, 0, 255]));
+        assert_eq!(frames[2].buffer().get_pixel(0, 0), &Rgba([255, 0, 0, 255]));
+
+        config["animation"] = json!({ "format": "webp", "frameDelay": 80 });
+        generate(&config);
+        assert_eq!(read_json(output.join("0.json"))["image"], "0.webp");
+        let decoder = WebPDecoder::new(BufReader::new(File::open(output.join("0.webp")).unwrap()));
+        let frames = decoder.unwrap().into_frames().collect_frames().unwrap();
+        assert_eq!(frames.len(), 3);
+        let delays: Vec<_> = frames.iter().map(|f| f.delay().numer_denom_ms()).collect();
+        assert_eq!(delays, [(80, 1); 3]);
+    }
+
+    #[test]
+    fn svg_layers() {
+        let dir = tempdir().unwrap();
+        let assets = dir.path().join("assets");
+        for layer in ["background", "face", "sparkle"] {
+            create_dir_all(assets.join(layer)).unwrap();
+        }
+        // Both layers use the same gradient ID, which must not clash once nested
+        let layer = |color: &str, shape: &str| {
+            format!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10">
+                <defs><linearGradient id="fill"><stop stop-color="{0}"/><stop offset="1" stop-color="{0}"/></linearGradient></defs>
+                {1}</svg>"#,
+                color, shape
+            )
+        };
+        write(
+            assets.join("background").join("blue.svg"),
+            layer(
+                "#0000ff",
+                r#"<rect width="10" height="10" fill="url(#fill)"/>"#,
+            ),
+        )
+        .unwrap();
+        write(
+            assets.join("face").join("red.svg"),
+            layer(
+                "#ff0000",
+                r#"<rect width="5" height="10" fill="url(#fill)"/>"#,
+            ),
+        )
+        .unwrap();
+        write_animation(
+            &assets.join("sparkle").join("sparkle.gif"),
+            &[[0, 0, 0, 0], [255, 255, 255, 255]],
+            100,
+        );
+
+        let mut config = minimal_config(json!({
+            "attributes": {
+                "background": { "_": { "blue.svg": 1 } },
+                "face": { "_": { "red.svg": 1 } }
+            },
+            "svg": { "width": 20 }
+        }));
+        let generate = |config: &Value| generate_art(config, dir.path(), &assets);
+
+        let output = generate(&config);
+        let metadata = read_json(output.join("0.json"));
+        assert_eq!(metadata["image"], "0.png");
+        assert_eq!(
+            metadata["properties"]["files"],
+            json!([
+                { "uri": "0.png", "type": "image/png" },
+                { "uri": "0.svg", "type": "image/svg+xml" }
+            ])
+        );
+        let document = read_to_string(output.join("0.svg")).unwrap();
+        assert!(document.contains("l0-fill") && document.contains("l1-fill"));
+        let image = image::open(output.join("0.png")).unwrap().into_rgba8();
+        assert_eq!(image.dimensions(), (20, 20));
+        assert_eq!(image.get_pixel(2, 10), &Rgba([255, 0, 0, 255]));
+        assert_eq!(image.get_pixel(18, 10), &Rgba([0, 0, 255, 255]));
+
+        config["svg"]["output"] = json!("svg");
+        generate(&config);
+        let metadata = read_json(output.join("0.json"));
+        assert_eq!(metadata["image"], "0.svg");
+        assert_eq!(metadata["properties"]["files"].as_array().unwrap().len(), 1);
+
+        // Mixed with other layers, SVG layers are rasterized and composited with them
+        config["attributes"]["sparkle"] = json!({ "_": { "sparkle.gif": 1 } });
+        config["svg"] = json!({ "width": 4, "height": 4 });
+        generate(&config);
+        assert_eq!(read_json(output.join("0.json"))["image"], "0.gif");
+        let decoder = GifDecoder::new(BufReader::new(File::open(output.join("0.gif")).unwrap()));
+        let frames = decoder.unwrap().into_frames().collect_frames().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].buffer().get_pixel(0, 0), &Rgba([255, 0, 0, 255]));
+        assert_eq!(frames[0].buffer().get_pixel(3, 0), &Rgba([0, 0, 255, 255]));
+        assert!(!output.join("0.layers").exists());
+    }
+
+    #[test]
+    fn image_output() {
+        let dir = tempdir().unwrap();
+        let assets = dir.path().join("assets");
+        create_dir_all(assets.join("background")).unwrap();
+        write(
+            assets.join("background").join("checks.svg"),
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="4" height="2">
+            <rect width="4" height="2" fill="#ff0000"/><rect width="1" height="1" fill="#0000ff"/></svg>"##,
+        )
+        .unwrap();
+
+        let config = minimal_config(json!({
+            "attributes": { "background": { "_": { "checks.svg": 1 } } },
+            "svg": { "output": "png" },
+            "image": {
+                "format": "jpeg",
+                "quality": 80,
+                "width": 8,
+                "variants": {
+                    "pixel": { "width": 40, "filter": "nearest", "format": "png", "pngOptimization": 2 },
+                    "thumbnail": { "height": 1, "format": "webp" }
+                }
+            }
+        }));
+        let output = generate_art(&config, dir.path(), &assets);
+
+        let metadata = read_json(output.join("0.json"));
+        assert_eq!(metadata["image"], "0.jpg");
+        assert_eq!(
+            metadata["properties"]["files"],
+            json!([
+                { "uri": "0.jpg", "type": "image/jpeg" },
+                { "uri": "0-pixel.png", "type": "image/png" },
+                { "uri": "0-thumbnail.webp", "type": "image/webp" }
+            ])
+        );
+        assert!(!output.join("0.png").exists());
+        let size = |file: &str| {
+            image::open(output.join(file))
+                .unwrap()
+                .into_rgba8()
+                .dimensions()
+        };
+        assert_eq!(size("0.jpg"), (8, 4));
+        assert_eq!(size("0-thumbnail.webp"), (2, 1));
+        let pixel = image::open(output.join("0-pixel.png"))
+            .unwrap()
+            .into_rgba8();
+        assert_eq!(pixel.dimensions(), (40, 20));
+        assert_eq!(pixel.get_pixel(9, 9), &Rgba([0, 0, 255, 255]));
+        assert_eq!(pixel.get_pixel(10, 9), &Rgba([255, 0, 0, 255]));
+
+        let mut config = config;
+        config["image"]["quality"] = json!(0);
+        let config_path = dir.path().join("config.json");
+        write(&config_path, config.to_string()).unwrap();
+        assert!(crate::config::parse_tiers(config_path.to_str().unwrap(), &[]).is_err());
+    }
+
+    #[test]
+    fn pixel_art_scale() {
+        let dir = tempdir().unwrap();
+        let assets = dir.path().join("assets");
+        for layer in ["background", "face"] {
+            create_dir_all(assets.join(layer)).unwrap();
+        }
+        let mut background = RgbaImage::from_pixel(4, 4, Rgba([0, 0, 255, 255]));
+        background.put_pixel(0, 0, Rgba([255, 255, 255, 255]));
+        background
+            .save(assets.join("background").join("blue.png"))
+            .unwrap();
+        RgbaImage::new(5, 5)
+            .save(assets.join("background").join("large.png"))
+            .unwrap();
+        write_animation(
+            &assets.join("face").join("blink.gif"),
+            &[[0, 0, 0, 0], [255, 0, 0, 255]],
+            100,
+        );
+
+        let mut config = minimal_config(json!({
+            "attributes": {
+                "background": { "_": { "blue.png": 1 } },
+                "face": { "_": { "blink.gif": 1 } }
+            },
+            "image": { "scale": 4, "variants": { "small": { "width": 4 } } }
+        }));
+        let generate = |config: &Value| generate_art(config, dir.path(), &assets);
+
+        let output = generate(&config);
+        let decoder = GifDecoder::new(BufReader::new(File::open(output.join("0.gif")).unwrap()));
+        let frames = decoder.unwrap().into_frames().collect_frames().unwrap();
+        let first = frames[0].buffer();
+        assert_eq!(first.dimensions(), (16, 16));
+        assert_eq!(first.get_pixel(3, 3), &Rgba([255, 255, 255, 255]));
+        assert_eq!(first.get_pixel(4, 3), &Rgba([0, 0, 255, 255]));
+        assert_eq!(frames[1].buffer().get_pixel(3, 3), &Rgba([255, 0, 0, 255]));
+        // Variants with their own size aren't scaled
+        let small = image::open(output.join("0-small.png")).unwrap();
+        assert_eq!(small.width(), 4);
+
+        config["image"]["scale"] = json!(65);
+        let config_path = dir.path().join("config.json");
+        write(&config_path, config.to_string()).unwrap();
+        assert!(crate::config::parse_tiers(config_path.to_str().unwrap(), &[]).is_err());
+        config["image"]["scale"] = json!(4);
+        // Smaller factors can still be too much for a large canvas
+        assert_eq!(output::scaled_size((4, 4), 64), Some((256, 256)));
+        assert_eq!(output::scaled_size((u32::MAX / 32, 1), 64), None);
+
+        // Layers of different sizes can't be scaled cleanly and are reported before drawing
+        config["attributes"]["background"] = json!({ "_": { "large.png": 1 } });
+        let config: Config = serde_json::from_value(config).unwrap();
+        let problems = art::validate_layers(&config, assets.to_str().unwrap(), &mut HashMap::new());
+        assert_eq!(problems.errors.len(), 1);
+        assert!(problems.errors[0].contains("blink.gif is 4x4 but the other layers are 5x5"));
+    }
+
+    #[test]
+    fn layer_validation() {
+        let dir = tempdir().unwrap();
+        let assets = dir.path().join("assets");
+        for layer in ["background", "face", "hat"] {
+            create_dir_all(assets.join(layer)).unwrap();
+        }
+        // The bottom layer may be opaque
+        RgbImage::from_pixel(4, 4, Rgb([0, 0, 255]))
+            .save(assets.join("background").join("blue.png"))
+            .unwrap();
+        write_animation(
+            &assets.join("face").join("blink.gif"),
+            &[[0, 255, 0, 255], [0, 0, 0, 255]],
+            100,
+        );
+        RgbImage::new(4, 4)
+            .save(assets.join("face").join("opaque.png"))
+            .unwrap();
+        RgbaImage::new(5, 5)
+            .save(assets.join("face").join("large.png"))
+            .unwrap();
+        write(assets.join("face").join("broken.png"), "not an image").unwrap();
+        RgbaImage::from_pixel(2, 2, Rgba([255, 0, 0, 255]))
+            .save(assets.join("hat").join("dot.png"))
+            .unwrap();
+        RgbaImage::new(3, 3)
+            .save(assets.join("hat").join("wide.png"))
+            .unwrap();
+
+        let mut config = minimal_config(json!({
+            "attributes": {
+                "background": { "_": { "blue.png": 1 } },
+                "face": { "_": {
+                    "blink.gif": 1, "opaque.png": 0, "large.png": 0,
+                    "broken.png": 0, "missing.png": 0
+                } },
+                "hat": { "_": { "dot.png": 1, "wide.png": 0 } }
+            },
+            "layers": {
+                "hat": { "traits": {
+                    "dot.png": { "offset": [2, 2] },
+                    "wide.png": { "offset": [2, 2] }
+                } }
+            }
+        }));
+        let parsed: Config = serde_json::from_value(config.clone()).unwrap();
+        let problems = art::validate_layers(&parsed, assets.to_str().unwrap(), &mut HashMap::new());
+        let expected = [
+            "large.png is 5x5 but the other layers are 4x4, resize it or give it an offset",
+            "broken.png is not a readable image",
+            "missing.png does not exist",
+            "wide.png is 3x3 and does not fit in the 4x4 canvas at offset [2, 2]",
+        ];
+        assert_eq!(
+            problems.errors.len(),
+            expected.len(),
+            "{:?}",
+            problems.errors
+        );
+        for problem in expected {
+            assert!(
+                problems.errors.iter().any(|p| p.contains(problem)),
+                "{}",
+                problem
+            );
+        }
+        // Opaque files above the bottom layer still composite
+        assert_eq!(problems.warnings.len(), 1);
+        assert!(problems.warnings[0]
+            .contains("opaque.png has no alpha channel, it hides every layer below it"));
+
+        // resin check fails on the same problems, so it can gate a pipeline
+        let config_path = dir.path().join("config.json");
+        let check = |config: &Value| {
+            write(&config_path, config.to_string()).unwrap();
+            panic::catch_unwind(|| {
+                check::handle(Check {
+                    config: config_path.to_str().unwrap().to_string(),
+                    assets: assets.to_str().unwrap().to_string(),
+                    set: vec![],
+                })
+            })
+        };
+        assert!(check(&config).is_err());
+
+        // Files with an offset are drawn at it
+        config["attributes"]["face"] = json!({ "_": { "blink.gif": 1 } });
+        config["attributes"]["hat"] = json!({ "_": { "dot.png": 1 } });
+        assert!(check(&config).is_ok());
+        let output = generate_art(&config, dir.path(), &assets);
+
+        let file = File::open(output.join("0.gif")).unwrap();
+        let frames = GifDecoder::new(BufReader::new(file))
+            .unwrap()
+            .into_frames()
+            .collect_frames()
+            .unwrap();
+        let first = frames[0].buffer();
+        assert_eq!(first.get_pixel(1, 1), &Rgba([0, 255, 0, 255]));
+        assert_eq!(first.get_pixel(2, 2), &Rgba([255, 0, 0, 255]));
+        assert_eq!(first.get_pixel(3, 3), &Rgba([255, 0, 0, 255]));
+    }
+}
+
+#[cfg(test)]
+mod init {
+    use crate::{
+        cmd::init,
+        config::{self, Attribute, GuaranteedRoll, NamedPosition, Position},
+        Init,
+    };
+    use indexmap::IndexMap;
+    use std::fs::{create_dir_all, read_to_string, write, File};
+    use tempfile::tempdir;
+
+    #[test]
+    fn from_scratch() {
+        let dir = tempdir().unwrap().path().join("assets");
+        let command_input = Init {
+            folder: dir.to_str().unwrap().to_string(),
+            overwrite: false,
+            from_existing: None,
+        };
+        init::handle(command_input);
+
+        let parsed_config = config::parse(dir.join("config.json").to_str().unwrap()).unwrap();
+        assert!(dir.join(config::SCHEMA_FILE).exists());
+
+        assert_eq!(parsed_config.version, Some(config::CONFIG_VERSION));
+        assert_eq!(parsed_config.name, "NFT Title");
+        assert_eq!(parsed_config.collection_name, "NFT Collection");
+        assert_eq!(parsed_config.symbol, "SNFT");
+        assert_eq!(parsed_config.description, "Hello, NFT!");
+        assert_eq!(parsed_config.external_url, "https://example.com");
+        assert_eq!(parsed_config.attributes.len(), 3);
+
+        let layer_1 = parsed_config.attributes.get("LAYER_NAME").unwrap();
+        if let Attribute::Keyed(a) = layer_1.get("_").unwrap() {
+            assert_eq!(a.len(), 1);
+            assert_eq!(a.get("FILE_NAME.png").unwrap(), &0.01f32);
+        } else {
+            panic!("wasn't able to deserialize LAYER_NAME key into keyed attribute")
+        }
+
+        let layer_2 = parsed_config.attributes.get("LAYER_NAME_2").unwrap();
+        if let Attribute::Keyed(a) = layer_2.get("_").unwrap() {
+            assert_eq!(a.len(), 1);
+            assert_eq!(a.get("FILE_NAME_2.png").unwrap(), &0.01f32);
+        } else {
+            panic!("wasn't able to deserialize LAYER_NAME_2 key into keyed attribute")
+        }
+        if let Attribute::Keyed(a) = layer_2.get("KEY").unwrap() {
+            assert_eq!(a.len(), 1);
+            assert_eq!(a.get("FILE_NAME_3.png").unwrap(), &0.01f32);
+        } else {
+            panic!("wasn't able to deserialize LAYER_NAME_2 key into keyed attribute")
+        }
+
+        assert_eq!(parsed_config.guaranteed_attribute_rolls.len(), 1);
+        assert_eq!(
+            parsed_config.guaranteed_attribute_rolls[0],
+            GuaranteedRoll::Detailed {
+                traits: IndexMap::from([
+                    ("LAYER_NAME".to_string(), "FILE_NAME.png".to_string()),
+                    ("LAYER_NAME_2".to_string(), "FILE_NAME_2.png".to_string()),
+                ]),
+                position: Some(Position::Named(NamedPosition::Random)),
+            }
+        );
+        assert_eq!(parsed_config.amount, 10);
+    }
+
+    #[test]
+    fn from_existing() {
+        let dir = tempdir().unwrap();
+        let dir_path = dir.path();
+
+        let dirs_to_create = vec!["attribute 1", "attribute 2", "attribute 3"];
+        for dir in &dirs_to_create {
+            create_dir_all(dir_path.join(dir)).unwrap();
+        }
+
+        let files_to_create = vec!["file 1.png", "file 2.png", "file 3.png"];
+        for file in files_to_create {
+            for dir in &dirs_to_create {
+                File::create(dir_path.join(dir).join(file)).unwrap();
+            }
+        }
+
+        let command_input = Init {
+            folder: "".to_string(),
+            overwrite: false,
+            from_existing: dir_path.to_str().map(str::to_string),
+        };
+        init::handle(command_input);
+
+        let parsed_config = config::parse(dir_path.join("config.json").to_str().unwrap()).unwrap();
+        let raw = read_to_string(dir_path.join("config.json")).unwrap();
+        assert!(raw.starts_with(r#"{"$schema":"./config.schema.json","version":1,"#));
+
+        assert_eq!(parsed_config.name, "NFT Title");
+        assert_eq!(parsed_config.collection_name, "NFT Collection");
+        assert_eq!(parsed_config.symbol, "SNFT");
+        assert_eq!(parsed_config.description, "Hello, NFT!");
+        assert_eq!(parsed_config.external_url, "https://example.com");
+        assert_eq!(parsed_config.attributes.len(), 3);
+
+        let layer_1 = parsed_config.attributes.get("attribute 1").unwrap();
+        if let Attribute::Keyed(a) = layer_1.get("_").unwrap() {
+            assert_eq!(a.len(), 3);
+            assert_eq!(a.get("file 1.png").unwrap(), &0.1f32);
+        } else {
+            panic!("wasn't able to deserialize attribute 1 key into keyed attribute")
+        }
+
+        let layer_2 = parsed_config.attributes.get("attribute 2").unwrap();
+        if let Attribute::Keyed(a) = layer_2.get("_").unwrap() {
+            assert_eq!(a.len(), 3);
+            assert_eq!(a.get("file 2.png").unwrap(), &0.1f32);
+        } else {
+            panic!("wasn't able to deserialize attribute 2 key into keyed attribute")
+        }
+
+        assert_eq!(parsed_config.guaranteed_attribute_rolls.len(), 0);
+        assert_eq!(parsed_config.amount, 10);
+    }
+
+    #[test]
+    fn from_existing_conventions() {
+        let dir = tempdir().unwrap();
+        let dir_path = dir.path();
+        let files = [
+            "02_face/cyan-face.png",
+            "02_face/gold-face#5.png",
+            "02_face/joker/clown-face#2.png",
+            "02_face/joker/weights.txt",
+            "01_background/blue.png",
+            "01_background/red.png",
+            "01_background/_weights.json",
+            "eyes/egg-eyes.png",
+        ];
+        for file in files {
+            let path = dir_path.join(file);
+            create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path).unwrap();
+        }
+        write(
+            dir_path.join("01_background/_weights.json"),
+            r#"{ "red.png": 3 }"#,
+        )
+        .unwrap();
+        write(
+            dir_path.join("02_face/joker/weights.txt"),
+            "clown-face#2.png 7\n",
+        )
+        .unwrap();
+
+        init::handle(Init {
+            folder: "".to_string(),
+            overwrite: false,
+            from_existing: dir_path.to_str().map(str::to_string),
+        });
+        let config = config::parse(dir_path.join("config.json").to_str().unwrap()).unwrap();
+
+        let layers: Vec<&String> = config.attributes.keys().collect();
+        assert_eq!(layers, ["_key", "01_background", "02_face", "eyes"]);
+
+        let weights = |layer: &str, key: &str| match &config.attributes[layer][key] {
+            Attribute::Keyed(a) => a.clone(),
+            Attribute::Standard(_) => panic!("{} should be keyed", key),
+        };
+        let background = weights("01_background", "_");
+        assert_eq!(background["blue.png"], 0.1);
+        assert_eq!(background["red.png"], 3.0);
+        assert_eq!(background.len(), 2);
+
+        let face = weights("02_face", "_");
+        assert_eq!(face["cyan-face.png"], 0.1);
+        assert_eq!(face["gold-face#5.png"], 5.0);
+        let joker = weights("02_face", "joker");
+        assert_eq!(joker["joker/clown-face#2.png"], 7.0);
+        assert_eq!(joker.len(), 1);
+
+        let face_layer = &config.layers["02_face"];
+        assert_eq!(face_layer.display_name.as_deref(), Some("face"));
+        assert_eq!(
+            face_layer.traits["gold-face#5.png"].display_name.as_deref(),
+            Some("gold-face")
+        );
+        assert!(!config.layers.contains_key("eyes"));
+    }
+
+    #[test]
+    fn from_existing_nested() {
+        let dir = tempdir().unwrap();
+        let dir_path = dir.path();
+        let files = [
+            "background/blue.png",
+            "face/cyan-face.png",
+            "face/joker/clown-face.png",
+            "face/joker/hats/top-hat-face.png",
+            "face/robot&night/steel-face.png",
+            "face/background:blue.png/sky-face.png",
+            "eyes/joker/crazy-eyes.png",
+        ];
+        for file in files {
+            let path = dir_path.join(file);
+            create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path).unwrap();
+        }
+
+        init::handle(Init {
+            folder: "".to_string(),
+            overwrite: false,
+            from_existing: dir_path.to_str().map(str::to_string),
+        });
+        let config = config::parse(dir_path.join("config.json").to_str().unwrap()).unwrap();
+
+        assert_eq!(config.attributes.keys().next().unwrap(), "_key");
+        let keys: Vec<&String> = config.attributes["_key"].keys().collect();
+        // eyes has no default files, so a key is always rolled
+        assert_eq!(keys, ["joker"]);
+
+        let face = &config.attributes["face"];
+        let tables: Vec<&String> = face.keys().collect();
+        // robot&night needs two keys at once and is skipped
+        assert_eq!(tables, ["_", "background:blue", "joker"]);
+        match &face["background:blue"] {
+            Attribute::Keyed(a) => {
+                assert!(a.contains_key("background:blue.png/sky-face.png"))
+            }
+            Attribute::Standard(_) => panic!("background:blue should be keyed"),
+        }
+        match &face["joker"] {
+            Attribute::Keyed(a) => {
+                let files: Vec<&String> = a.keys().collect();
+                assert_eq!(
+                    files,
+                    ["joker/clown-face.png", "joker/hats/top-hat-face.png"]
+                );
+            }
+            Attribute::Standard(_) => panic!("joker should be keyed"),
+        }
+        assert!(!config.attributes["eyes"].contains_key("_"));
+    }
+
+    #[test]
+    #[should_panic]
+    fn directory_already_exists() {
+        let dir = tempdir().unwrap();
+        let command_input = Init {
             folder: dir.path().to_str().unwrap().to_string(),
             overwrite: false,
             from_existing: None,
@@ -342,3 +1921,529 @@ mod init {
         init::handle(command_input);
     }
 }
+
+#[cfg(test)]
+mod sync {
+    use crate::{
+        cmd::sync::{handle, sync},
+        format::Format,
+        Sync,
+    };
+    use serde_json::{json, Value};
+    use std::fs::{create_dir_all, read_to_string, write, File};
+    use tempfile::tempdir;
+
+    #[test]
+    fn sync_assets() {
+        let dir = tempdir().unwrap();
+        let files = [
+            "background/blue.png",
+            "background/red.png",
+            "face/cyan-face.png",
+            "face/gold-face#5.png",
+            "face/joker/clown-face.png",
+            "eyes/egg-eyes.png",
+            "one-of-ones/founder.png",
+        ];
+        for file in files {
+            let path = dir.path().join(file);
+            create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path).unwrap();
+        }
+        let mut config = json!({
+            "name": "Test",
+            "attributes": {
+                "background": { "_": { "blue.png": 3, "green.png": 1 } },
+                "face": { "cyan-face.png": 2 }
+            },
+            "oneOfOnes": "one-of-ones",
+            "amount": 5
+        });
+
+        let changes = sync(&mut config, dir.path(), false);
+        assert_eq!(
+            changes,
+            [
+                "+ background/red.png (0.1)",
+                "+ eyes/ (1 files)",
+                "+ face/gold-face#5.png (5)",
+                "+ face/joker/clown-face.png (0.1)",
+                "! background/green.png is missing",
+                "+ _key/_ (0.1)",
+                "+ _key/joker (0.1)",
+            ]
+        );
+        assert_eq!(
+            config["attributes"],
+            json!({
+                "_key": { "_": 0.1, "joker": 0.1 },
+                "background": { "_": { "blue.png": 3, "green.png": 1, "red.png": 0.1 } },
+                "face": {
+                    "cyan-face.png": 2,
+                    "gold-face#5.png": 5.0,
+                    "joker": { "joker/clown-face.png": 0.1 }
+                },
+                "eyes": { "_": { "egg-eyes.png": 0.1 } }
+            })
+        );
+        assert_eq!(
+            config["layers"]["face"]["traits"]["gold-face#5.png"]["displayName"],
+            "gold-face"
+        );
+
+        let changes = sync(&mut config, dir.path(), true);
+        assert_eq!(changes, ["- background/green.png"]);
+        let background: Vec<&String> = config["attributes"]["background"]["_"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .collect();
+        assert_eq!(background, ["blue.png", "red.png"]);
+
+        assert!(sync(&mut config, dir.path(), true).is_empty());
+    }
+
+    #[test]
+    fn percent_weights() {
+        let dir = tempdir().unwrap();
+        for file in [
+            "background/blue.png",
+            "background/red.png",
+            "eyes/egg-eyes.png",
+        ] {
+            let path = dir.path().join(file);
+            create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path).unwrap();
+        }
+        let mut config = json!({
+            "attributes": { "background": { "_": { "blue.png": 100 } } },
+            "weightMode": "percent"
+        });
+        let before = config.clone();
+
+        let changes = sync(&mut config, dir.path(), false);
+        assert_eq!(
+            changes,
+            [
+                "! background/red.png is new, add it by hand so its table still adds up to 100",
+                "! eyes/ (1 files) is new, add it by hand so its table still adds up to 100",
+            ]
+        );
+        assert_eq!(config, before);
+    }
+
+    #[test]
+    fn keeps_comments() {
+        let dir = tempdir().unwrap();
+        for file in ["background/blue.png", "background/red.png"] {
+            let path = dir.path().join(file);
+            create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path).unwrap();
+        }
+        let config_path = dir.path().join("config.json");
+        let text = r#"{
+    // Shown on marketplaces
+    "name": "Test",
+    "attributes": {
+        "background": {
+            "_": { "blue.png": 3 } // Rare
+        }
+    },
+    "amount": 5 /* tokens */
+}"#;
+        write(&config_path, text).unwrap();
+
+        handle(Sync {
+            assets: dir.path().to_str().unwrap().to_string(),
+            config: config_path.to_str().unwrap().to_string(),
+            remove: false,
+            dry_run: false,
+        });
+        let updated = read_to_string(&config_path).unwrap();
+        for comment in ["// Shown on marketplaces", "// Rare", "/* tokens */"] {
+            assert!(updated.contains(comment), "{}", updated);
+        }
+        let config: Value = Format::Json.parse(&updated).unwrap();
+        assert_eq!(
+            config["attributes"]["background"]["_"],
+            json!({ "blue.png": 3, "red.png": 0.1 })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "uses include")]
+    fn composed_config() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("config.json");
+        write(
+            &config_path,
+            r#"{ "include": ["layers"], "name": "Test", "amount": 5 }"#,
+        )
+        .unwrap();
+
+        handle(Sync {
+            assets: dir.path().to_str().unwrap().to_string(),
+            config: config_path.to_str().unwrap().to_string(),
+            remove: false,
+            dry_run: true,
+        });
+    }
+}
+
+#[cfg(test)]
+mod format {
+    use crate::{
+        config::{self, Config},
+        format::Format,
+    };
+    use serde_json::{json, Value};
+    use std::fs::write;
+    use tempfile::tempdir;
+
+    #[test]
+    fn example_config() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        write(&path, include_str!("../config.example.json")).unwrap();
+        let config = config::parse_tiers(path.to_str().unwrap(), &[])
+            .unwrap()
+            .remove(0)
+            .config;
+        assert_eq!(config.amount, 5);
+        assert_eq!(config.layers["mouth"].none_probability, Some(0.1));
+        assert!(
+            config::weight_warnings(&config).is_empty(),
+            "{:?}",
+            config::weight_warnings(&config)
+        );
+    }
+
+    #[test]
+    fn comments() {
+        let text = r#"{
+            // "amount": 1,
+            "name": "// not a comment", /* inline */
+            "list": [1, 2, /* "three" */],
+            "escaped": "quote \" // still a string",
+        }"#;
+        let value: Value = Format::Json.parse(text).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "name": "// not a comment",
+                "list": [1, 2],
+                "escaped": "quote \" // still a string"
+            })
+        );
+    }
+
+    #[test]
+    fn error_location() {
+        let json = "{\n    // comment\n    \"amount\": \"five\"\n}";
+        let error = Format::Json
+            .parse::<Config>(json)
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.contains("line 3 column"), "{}", error);
+
+        let json5 = "{\n    // comment\n    amount: 5,,\n}";
+        let error = Format::Json5.parse::<Value>(json5).unwrap_err().to_string();
+        assert!(error.contains("at line 3 column"), "{}", error);
+
+        let yaml = "name: Test\namount: [5\n";
+        let error = Format::Yaml.parse::<Value>(yaml).unwrap_err().to_string();
+        assert!(error.contains("at line"), "{}", error);
+
+        let toml = "name = \"Test\"\namount = \n";
+        let error = Format::Toml.parse::<Value>(toml).unwrap_err().to_string();
+        assert!(error.contains("at line 2 column"), "{}", error);
+    }
+
+    #[test]
+    fn update_keeps_comments() {
+        let json5 =
+            "{\n    // Shown on marketplaces\n    name: 'Test',\n    amount: 5, /* tokens */\n}";
+        let mut value: Value = Format::Json5.parse(json5).unwrap();
+        value["amount"] = json!(10);
+        value["symbol"] = json!("TST");
+        let updated = Format::Json5.update(json5, &value).unwrap();
+        assert_eq!(
+            updated,
+            "{\n    // Shown on marketplaces\n    name: 'Test',\n    amount: 10, /* tokens */\n    \"symbol\": \"TST\",\n}"
+        );
+        assert_eq!(Format::Json5.parse::<Value>(&updated).unwrap(), value);
+
+        let yaml = "name: \"#1\"\namount: 5 # tokens\n";
+        let value: Value = Format::Yaml.parse(yaml).unwrap();
+        let error = Format::Yaml.update(yaml, &value).unwrap_err().to_string();
+        assert!(error.contains("comments can't be kept"), "{}", error);
+        assert!(Format::Yaml.update("name: \"#1\"\n", &value).is_ok());
+    }
+
+    #[test]
+    fn formats_by_extension() {
+        let json = r#"{
+            name: "Test", collectionName: "Test", symbol: "TST", description: "", externalUrl: "",
+            attributes: { background: { _: { "blue.png": 1, "red.png": 2 } } },
+            guaranteedAttributeRolls: [],
+            amount: 5, // JSON5 allows unquoted keys
+        }"#;
+        let yaml = r#"
+name: Test
+collectionName: Test
+symbol: TST
+description: ""
+externalUrl: ""
+attributes:
+  background:
+    _:
+      blue.png: 1
+      red.png: 2
+guaranteedAttributeRolls: []
+amount: 5
+"#;
+        let toml = r#"
+name = "Test"
+collectionName = "Test"
+symbol = "TST"
+description = ""
+externalUrl = ""
+guaranteedAttributeRolls = []
+amount = 5
+
+[attributes.background._]
+"blue.png" = 1
+"red.png" = 2
+"#;
+
+        let dir = tempdir().unwrap();
+        for (file, text) in [
+            ("config.json5", json),
+            ("config.yaml", yaml),
+            ("config.toml", toml),
+        ] {
+            let path = dir.path().join(file);
+            write(&path, text).unwrap();
+            let config = config::parse(path.to_str().unwrap()).unwrap();
+            assert_eq!(config.amount, 5, "{}", file);
+            assert_eq!(config.attributes["background"].len(), 1, "{}", file);
+        }
+    }
+}
+
+#[cfg(test)]
+mod migrate {
+    use crate::{
+        cmd::migrate::{handle, migrate},
+        config::{self, Config, GuaranteedRoll, CONFIG_VERSION},
+        format::Format,
+        Migrate,
+    };
+    use indexmap::IndexMap;
+    use serde_json::{json, Value};
+    use std::fs::{read_to_string, write};
+    use tempfile::tempdir;
+
+    fn legacy_config() -> Value {
+        json!({
+            "name": "Very Special NFT",
+            "symbol": "SNFT",
+            "description": "",
+            "externalUrl": "",
+            "creators": [{ "address": "ADDRESS", "share": 100 }],
+            "royaltyPercentage": 10,
+            "collection": { "name": "Special NFTs", "family": "Specials" },
+            "attributes": {
+                "_key": { "joker": 1 },
+                "background": { "blue.png": 1, "red.png": 2 },
+                "face": { "joker": { "gold-face.png": 1 }, "_": { "cyan-face.png": 1 } }
+            },
+            "guaranteedAttributeRolls": [["blue.png", "cyan-face.png"]],
+            "amount": 10
+        })
+    }
+
+    #[test]
+    fn legacy() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        write(&path, legacy_config().to_string()).unwrap();
+        let error = config::parse(path.to_str().unwrap()).err().unwrap();
+        assert!(error.to_string().contains("resin migrate"), "{}", error);
+
+        let mut config = legacy_config();
+        let changes = migrate(&mut config);
+        assert_eq!(
+            changes,
+            [
+                "Moved collection.name to collectionName \"Special NFTs\"",
+                "Removed collection.family, it is not used",
+                "Moved 2 weights in attributes.background into its \"_\" table",
+                "Rewrote guaranteed roll 0 as a map of layers to files",
+                "Set version to 1",
+            ]
+        );
+        assert_eq!(
+            config.as_object().unwrap().keys().next().unwrap(),
+            "version"
+        );
+        assert_eq!(
+            config["attributes"]["background"],
+            json!({ "_": { "blue.png": 1, "red.png": 2 } })
+        );
+        // Unused fields are kept
+        assert_eq!(config["royaltyPercentage"], 10);
+
+        let parsed: Config = serde_json::from_value(config.clone()).unwrap();
+        assert_eq!(parsed.version, Some(CONFIG_VERSION));
+        assert_eq!(parsed.collection_name, "Special NFTs");
+        assert_eq!(
+            parsed.guaranteed_attribute_rolls[0],
+            GuaranteedRoll::Traits(IndexMap::from([
+                ("background".to_string(), "blue.png".to_string()),
+                ("face".to_string(), "cyan-face.png".to_string()),
+            ]))
+        );
+
+        write(&path, config.to_string()).unwrap();
+        assert!(config::parse(path.to_str().unwrap()).is_ok());
+        assert!(migrate(&mut config).is_empty());
+    }
+
+    #[test]
+    fn keeps_comments() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        let text = r#"{
+    // Shown on marketplaces
+    "name": "Very Special NFT",
+    "collection": { "name": "Special NFTs" },
+    "amount": 10 // tokens
+}"#;
+        write(&path, text).unwrap();
+
+        handle(Migrate {
+            config: path.to_str().unwrap().to_string(),
+            dry_run: false,
+        });
+        let updated = read_to_string(&path).unwrap();
+        assert!(updated.contains("// Shown on marketplaces"), "{}", updated);
+        assert!(updated.contains("\"amount\": 10, // tokens"), "{}", updated);
+        let config: Value = Format::Json.parse(&updated).unwrap();
+        assert_eq!(config["collectionName"], "Special NFTs");
+        assert_eq!(config["version"], CONFIG_VERSION);
+    }
+
+    #[test]
+    #[should_panic]
+    fn newer_version() {
+        let mut config = legacy_config();
+        config["version"] = json!(CONFIG_VERSION + 1);
+        migrate(&mut config);
+    }
+}
+
+#[cfg(test)]
+mod simulate {
+    use super::config::minimal_config;
+    use crate::{
+        config,
+        simulate::{simulate, NONE_TRAIT},
+    };
+    use rand::prelude::*;
+    use serde_json::json;
+    use std::fs::write;
+    use tempfile::tempdir;
+
+    #[test]
+    fn distribution() {
+        let config = minimal_config(json!({
+            "attributes": {
+                "_key": { "joker": 1, "plain": 3 },
+                "background": { "_": { "blue.png": 999, "rare.png": 1 } },
+                "face": {
+                    "joker": { "gold-face.png": 1 },
+                    "_": { "cyan-face.png": 1 }
+                }
+            },
+            "layers": { "background": { "noneProbability": 0.5 } },
+            "amount": 50
+        }));
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        write(&path, config.to_string()).unwrap();
+        let config = config::parse(path.to_str().unwrap()).unwrap();
+
+        let runs = 400;
+        let simulation = simulate(&config, runs, &mut StdRng::seed_from_u64(1));
+        assert_eq!(simulation.rolled, 50 * runs);
+
+        let gold = &simulation.layers["face"]["gold-face.png"];
+        let (low, high) = gold.confidence_interval(runs);
+        assert!((gold.mean(runs) - 0.25).abs() < 0.02, "{}", gold.mean(runs));
+        assert!(low < 0.25 && 0.25 < high, "{} - {}", low, high);
+        assert_eq!(gold.appears(runs), 1.0);
+
+        let none = &simulation.layers["background"][NONE_TRAIT];
+        assert!((none.mean(runs) - 0.5).abs() < 0.02);
+        // 25 backgrounds a run at 1 in 1000 each
+        let rare = simulation.layers["background"]["rare.png"].appears(runs);
+        assert!(rare > 0.01 && rare < 0.06, "{}", rare);
+    }
+}
+
+#[cfg(test)]
+mod odds {
+    use super::config::minimal_config;
+    use crate::{config, odds, simulate::NONE_TRAIT};
+    use serde_json::json;
+    use std::fs::write;
+    use tempfile::tempdir;
+
+    #[test]
+    fn conditional_odds() {
+        let config = minimal_config(json!({
+            "attributes": {
+                "_key": { "joker": 1, "plain": 3 },
+                "background": { "_": { "blue.png": 1 } },
+                "face": {
+                    "joker": { "gold-face.png": 1 },
+                    "_": { "cyan-face.png": 1, "teal-face.png": 1 }
+                },
+                "mouth": {
+                    "face:gold-face": { "grin.png": 1 },
+                    "_": { "smile.png": 1, "frown.png": 3 }
+                }
+            },
+            "layers": { "background": { "noneProbability": 0.5 } },
+            "amount": 10
+        }));
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        write(&path, config.to_string()).unwrap();
+        let config = config::parse(path.to_str().unwrap()).unwrap();
+
+        let combinations = [
+            odds::parse_combination("face:gold-face.png&mouth:grin.png", &config).unwrap(),
+            odds::parse_combination("_key:plain&mouth:smile.png", &config).unwrap(),
+            odds::parse_combination("_key:joker&mouth:smile.png", &config).unwrap(),
+        ];
+        assert!(odds::parse_combination("hat:top.png", &config).is_err());
+        let odds = odds::odds(&config, &combinations);
+
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+        assert!(close(odds.layers["_key"]["joker"], 0.25));
+        assert!(close(odds.layers["background"]["blue.png"], 0.5));
+        assert!(close(odds.layers["background"][NONE_TRAIT], 0.5));
+        assert!(close(odds.layers["face"]["gold-face.png"], 0.25));
+        assert!(close(odds.layers["face"]["teal-face.png"], 0.375));
+        assert!(close(odds.layers["mouth"]["grin.png"], 0.25));
+        assert!(close(odds.layers["mouth"]["smile.png"], 0.1875));
+        assert!(close(odds.layers["mouth"]["frown.png"], 0.5625));
+
+        assert!(close(odds.joint(&combinations[0]), 0.25));
+        assert!(close(odds.joint(&combinations[1]), 0.1875));
+        assert!(close(odds.joint(&combinations[2]), 0.0));
+    }
+}